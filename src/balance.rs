@@ -0,0 +1,47 @@
+use crate::config::MapBalance;
+
+impl MapBalance {
+    /// Maps of every part, each sorted by index. Pinned maps are placed first, the others go
+    /// heaviest first to the least loaded part, ties broken by the lower map index and part
+    /// number, so the same config always yields the same split.
+    pub fn split(&self) -> Vec<Vec<i64>> {
+        let mut parts: Vec<Vec<i64>> = vec![vec![]; self.parts];
+        let mut loads = vec![0; self.parts];
+
+        let (pinned, mut free): (Vec<_>, Vec<_>) = self
+            .maps
+            .iter()
+            .partition(|m| matches!(m.part, Some(p) if p >= 1 && p <= self.parts));
+
+        for m in pinned.iter() {
+            let i = m.part.unwrap() - 1;
+            parts[i].push(m.map);
+            loads[i] += m.weight;
+        }
+
+        free.sort_by_key(|m| (-m.weight, m.map));
+        for m in free.iter() {
+            let i = match (0..self.parts).min_by_key(|i| (loads[*i], *i)) {
+                Some(v) => v,
+                None => break,
+            };
+            parts[i].push(m.map);
+            loads[i] += m.weight;
+        }
+
+        for maps in parts.iter_mut() {
+            maps.sort_unstable();
+        }
+
+        parts
+    }
+
+    /// Sum of the weights of `maps`.
+    pub fn load(&self, maps: &[i64]) -> i64 {
+        self.maps
+            .iter()
+            .filter(|m| maps.contains(&m.map))
+            .map(|m| m.weight)
+            .sum()
+    }
+}
@@ -1,262 +1,1098 @@
-use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::{borrow::Cow, fs::read_to_string};
-
-use snafu::{ResultExt, Snafu};
-
-#[derive(Debug, Snafu)]
-pub enum ConfigError {
-    #[snafu(display("{} file not found", crate::CONFIG_FILE))]
-    NotFound,
-    #[snafu(display("cannot read config file: {}", source))]
-    Read { source: std::io::Error },
-    #[snafu(display("cannot parse config file: {}", source))]
-    Parse { source: serde_json::Error },
-}
-
-pub type ConfigResult<T, E = ConfigError> = std::result::Result<T, E>;
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Config {
-    #[serde(rename = "server_name")]
-    pub server_name: String,
-
-    #[serde(rename = "auth")]
-    pub auth: Auth,
-
-    #[serde(rename = "channels")]
-    pub channels: Channels,
-
-    #[serde(rename = "common")]
-    pub common: Common,
-
-    #[serde(rename = "db")]
-    pub db: Db,
-
-    #[serde(rename = "adminpage_ips")]
-    pub adminpage_ips: AdminpageIps,
-
-    #[serde(rename = "databases")]
-    pub databases: Databases,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AdminpageIps {
-    #[serde(rename = "adminpage_ip")]
-    pub adminpage_ip: String,
-
-    #[serde(rename = "adminpage_ip1")]
-    pub adminpage_ip1: String,
-
-    #[serde(rename = "adminpage_ip2")]
-    pub adminpage_ip2: String,
-
-    #[serde(rename = "adminpage_ip3")]
-    pub adminpage_ip3: String,
-
-    #[serde(rename = "password")]
-    pub password: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Auth {
-    #[serde(rename = "auth_server")]
-    pub auth_server: String,
-
-    #[serde(rename = "traffic_profile")]
-    pub traffic_profile: i64,
-
-    #[serde(rename = "ports")]
-    pub ports: Vec<Port>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Port {
-    #[serde(rename = "port")]
-    pub port: i64,
-
-    #[serde(rename = "p2p_port")]
-    pub p2p_port: i64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Channels {
-    #[serde(rename = "common_maps")]
-    pub common_maps: Vec<Vec<i64>>,
-
-    #[serde(rename = "settings")]
-    pub settings: Vec<Setting>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Setting {
-    #[serde(rename = "rename")]
-    pub rename: Option<String>,
-
-    #[serde(rename = "channel_id")]
-    pub channel_id: i64,
-
-    #[serde(rename = "port")]
-    pub port: i64,
-
-    #[serde(rename = "p2p_port")]
-    pub p2p_port: i64,
-
-    #[serde(rename = "override_maps")]
-    pub override_maps: Option<Vec<Vec<i64>>>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Common {
-    #[serde(rename = "table_postfix")]
-    pub table_postfix: String,
-
-    #[serde(rename = "passes_per_sec")]
-    pub passes_per_sec: i64,
-
-    #[serde(rename = "db_ip")]
-    pub db_ip: String,
-
-    #[serde(rename = "db_port")]
-    pub db_port: i64,
-
-    #[serde(rename = "save_event_second_cycle")]
-    pub save_event_second_cycle: i64,
-
-    #[serde(rename = "ping_event_second_cycle")]
-    pub ping_event_second_cycle: i64,
-
-    #[serde(rename = "view_range")]
-    pub view_range: i64,
-
-    #[serde(rename = "locale_service")]
-    pub locale_service: String,
-
-    #[serde(rename = "speedhack_limit_count")]
-    pub speedhack_limit_count: i64,
-
-    #[serde(rename = "speedhack_limit_bonus")]
-    pub speedhack_limit_bonus: i64,
-
-    #[serde(rename = "pk_protect_level")]
-    pub pk_protect_level: i64,
-
-    #[serde(rename = "mall_url")]
-    pub mall_url: String,
-
-    #[serde(rename = "traffic_profile")]
-    pub traffic_profile: i64,
-
-    #[serde(rename = "test_server")]
-    pub test_server: i64,
-
-    #[serde(rename = "max_level")]
-    pub max_level: i64,
-
-    #[serde(rename = "disable_item_bonus_change_time")]
-    pub disable_item_bonus_change_time: i64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Databases {
-    #[serde(rename = "player")]
-    pub player: Database,
-
-    #[serde(rename = "common")]
-    pub common: Database,
-
-    #[serde(rename = "hotbackup")]
-    pub hotbackup: Database,
-
-    #[serde(rename = "log")]
-    pub log: Database,
-
-    #[serde(rename = "account")]
-    pub account: Database,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Database {
-    #[serde(rename = "ip")]
-    pub ip: String,
-
-    #[serde(rename = "port")]
-    pub port: String,
-
-    #[serde(rename = "database")]
-    pub database: String,
-
-    #[serde(rename = "user")]
-    pub user: String,
-
-    #[serde(rename = "password")]
-    pub password: String,
-
-    #[serde(rename = "sock")]
-    pub sock: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Db {
-    #[serde(rename = "bind_port")]
-    pub bind_port: i64,
-
-    #[serde(rename = "db_sleep_msec")]
-    pub db_sleep_msec: i64,
-
-    #[serde(rename = "client_heart_fps")]
-    pub client_heart_fps: i64,
-
-    #[serde(rename = "hash_player_life_sec")]
-    pub hash_player_life_sec: i64,
-
-    #[serde(rename = "player_delete_level_limit")]
-    pub player_delete_level_limit: i64,
-
-    #[serde(rename = "player_id_start")]
-    pub player_id_start: i64,
-
-    #[serde(rename = "item_id_range")]
-    pub item_id_range: ItemIdRange,
-
-    #[serde(rename = "test_server")]
-    pub test_server: i64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ItemIdRange {
-    #[serde(rename = "start")]
-    pub start: i64,
-
-    #[serde(rename = "end")]
-    pub end: i64,
-}
-
-impl Setting {
-    pub fn channel_dir_name(&self) -> Cow<str> {
-        match self.rename {
-            Some(ref v) => Cow::Borrowed(v),
-            None => Cow::Owned(format!("channel{}", self.channel_id)),
-        }
-    }
-
-    pub fn get_map_ids<'a>(&'a self, channels: &'a Channels) -> &'a Vec<Vec<i64>> {
-        match self.override_maps {
-            Some(ref v) => v,
-            None => &channels.common_maps,
-        }
-    }
-}
-
-impl Config {
-    pub fn read_config() -> ConfigResult<Config> {
-        let path = format!("./{}", crate::CONFIG_FILE);
-        let file = Path::new(&path);
-        if !file.exists() {
-            return Err(ConfigError::NotFound);
-        }
-
-        let data = read_to_string(file).context(Read)?;
-        Ok(serde_json::from_str::<Config>(&*data).context(Parse)?)
-    }
-}
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{borrow::Cow, fs::read_to_string};
+
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum ConfigError {
+    #[snafu(display("{} file not found", path.display()))]
+    NotFound { path: PathBuf },
+    #[snafu(display("cannot read config file: {}", source))]
+    Read { source: std::io::Error },
+    #[snafu(display("cannot parse config file: {}", source))]
+    Parse { source: serde_json::Error },
+}
+
+pub type ConfigResult<T, E = ConfigError> = std::result::Result<T, E>;
+
+/// Additional `KEY: value` lines for cores reading keys this tool does not know about.
+pub type Extra = BTreeMap<String, Value>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(rename = "server_name")]
+    pub server_name: String,
+
+    /// Where the tree lives on the game server, scripts and units `cd` into it.
+    /// `/home/<server_name>` when unset
+    #[serde(rename = "install_dir", default)]
+    pub install_dir: Option<PathBuf>,
+
+    #[serde(rename = "auth")]
+    pub auth: Auth,
+
+    #[serde(rename = "channels")]
+    pub channels: Channels,
+
+    #[serde(rename = "common")]
+    pub common: Common,
+
+    #[serde(rename = "db")]
+    pub db: Db,
+
+    #[serde(rename = "adminpage_ips")]
+    pub adminpage_ips: AdminpageIps,
+
+    #[serde(rename = "databases")]
+    pub databases: Databases,
+
+    /// Where the shared game files and binaries are and which of them each role links
+    #[serde(rename = "share", default)]
+    pub share: Share,
+
+    /// Ranges ports missing from channels and auth are allocated from
+    #[serde(rename = "port_ranges", default)]
+    pub port_ranges: Option<PortRanges>,
+
+    /// Ports allocated from `port_ranges`, keyed by `game<channel>_<part>` and `auth<id>`
+    #[serde(skip)]
+    pub allocated_ports: BTreeMap<String, AllocatedPorts>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Share {
+    /// Relative to the server directory unless absolute
+    #[serde(rename = "path", default = "default_share_path")]
+    pub path: PathBuf,
+
+    /// Linked into every channel part
+    #[serde(rename = "game", default = "default_game_links")]
+    pub game: Vec<ShareLink>,
+
+    /// Linked into every auth instance
+    #[serde(rename = "auth", default = "default_auth_links")]
+    pub auth: Vec<ShareLink>,
+
+    #[serde(rename = "db", default = "default_db_links")]
+    pub db: Vec<ShareLink>,
+
+    /// Core binary of channels and auth, `game_<server_name lowercase>` when unset
+    #[serde(rename = "game_binary", default)]
+    pub game_binary: Option<String>,
+
+    #[serde(rename = "db_binary", default = "default_db_binary")]
+    pub db_binary: String,
+
+    /// How each role gets its share entries and binary
+    #[serde(rename = "deploy", default)]
+    pub deploy: Deploy,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Deploy {
+    #[serde(rename = "game", default)]
+    pub game: DeployMode,
+
+    #[serde(rename = "auth", default)]
+    pub auth: DeployMode,
+
+    #[serde(rename = "db", default)]
+    pub db: DeployMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeployMode {
+    #[default]
+    Symlink,
+    /// Needs the tree on the same filesystem as the share directory
+    Hardlink,
+    /// Self-contained tree, refreshed when the checksum of the source changes
+    Copy,
+}
+
+impl Default for Share {
+    fn default() -> Self {
+        Share {
+            path: default_share_path(),
+            game: default_game_links(),
+            auth: default_auth_links(),
+            db: default_db_links(),
+            game_binary: None,
+            db_binary: default_db_binary(),
+            deploy: Deploy::default(),
+        }
+    }
+}
+
+fn default_share_path() -> PathBuf {
+    PathBuf::from("share")
+}
+
+fn default_game_links() -> Vec<ShareLink> {
+    vec![
+        ShareLink::new("data", ShareKind::Directory),
+        ShareLink::new("package", ShareKind::Directory),
+        ShareLink::new("CMD", ShareKind::File),
+        ShareLink::new("locale", ShareKind::Directory),
+    ]
+}
+
+fn default_auth_links() -> Vec<ShareLink> {
+    vec![
+        ShareLink::new("data", ShareKind::Directory),
+        ShareLink::new("locale", ShareKind::Directory),
+    ]
+}
+
+fn default_db_links() -> Vec<ShareLink> {
+    vec![
+        ShareLink::new("data", ShareKind::Directory),
+        ShareLink::new("package", ShareKind::Directory),
+        ShareLink::new("locale", ShareKind::Directory),
+        ShareLink::new("item_proto.txt", ShareKind::File),
+        ShareLink::new("item_names.txt", ShareKind::File),
+        ShareLink::new("mob_proto.txt", ShareKind::File),
+        ShareLink::new("mob_names.txt", ShareKind::File),
+    ]
+}
+
+fn default_db_binary() -> String {
+    "db".to_string()
+}
+
+/// What an entry of the share directory has to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareKind {
+    /// Only has to exist
+    Any,
+    Directory,
+    File,
+    Executable,
+}
+
+/// Entry of the share directory linked under the same name. Accepted either as a plain name
+/// or as an object with its `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "ShareLinkDefinition")]
+pub struct ShareLink {
+    #[serde(rename = "name")]
+    pub name: String,
+
+    #[serde(rename = "kind")]
+    pub kind: ShareKind,
+}
+
+impl ShareLink {
+    fn new(name: &str, kind: ShareKind) -> Self {
+        ShareLink {
+            name: name.to_string(),
+            kind,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ShareLinkDefinition {
+    Name(String),
+    Object {
+        #[serde(rename = "name")]
+        name: String,
+
+        #[serde(rename = "kind", default = "default_share_kind")]
+        kind: ShareKind,
+    },
+}
+
+fn default_share_kind() -> ShareKind {
+    ShareKind::Any
+}
+
+impl From<ShareLinkDefinition> for ShareLink {
+    fn from(definition: ShareLinkDefinition) -> Self {
+        match definition {
+            ShareLinkDefinition::Name(name) => ShareLink {
+                name,
+                kind: ShareKind::Any,
+            },
+            ShareLinkDefinition::Object { name, kind } => ShareLink { name, kind },
+        }
+    }
+}
+
+impl Share {
+    /// The share directory as seen from `root`.
+    pub fn dir(&self, root: &Path) -> PathBuf {
+        root.join(&self.path)
+    }
+
+    /// Target of a link to `name` placed in `dir`, a directory relative to the server directory.
+    pub fn link_target(&self, dir: &Path, name: &str) -> PathBuf {
+        if self.path.is_absolute() {
+            return self.path.join(name);
+        }
+
+        let mut target: PathBuf = dir.components().map(|_| "..").collect();
+        target.push(&self.path);
+        target.push(name);
+        target
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PortRanges {
+    /// Listening ports of channel parts
+    #[serde(rename = "game", default)]
+    pub game: Option<PortRange>,
+
+    /// P2P ports of channel parts and auth
+    #[serde(rename = "p2p", default)]
+    pub p2p: Option<PortRange>,
+
+    /// Listening ports of auth
+    #[serde(rename = "auth", default)]
+    pub auth: Option<PortRange>,
+}
+
+/// Inclusive range of ports.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PortRange {
+    #[serde(rename = "start")]
+    pub start: i64,
+
+    #[serde(rename = "end")]
+    pub end: i64,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: i64) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllocatedPorts {
+    #[serde(rename = "port")]
+    pub port: i64,
+
+    #[serde(rename = "p2p_port")]
+    pub p2p_port: i64,
+}
+
+pub fn part_port_key(channel_id: i64, part: usize) -> String {
+    format!("game{}_{}", channel_id, part)
+}
+
+pub fn auth_port_key(id: usize) -> String {
+    format!("auth{}", id)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminpageIps {
+    #[serde(rename = "adminpage_ip")]
+    pub adminpage_ip: String,
+
+    #[serde(rename = "adminpage_ip1")]
+    pub adminpage_ip1: String,
+
+    #[serde(rename = "adminpage_ip2")]
+    pub adminpage_ip2: String,
+
+    #[serde(rename = "adminpage_ip3")]
+    pub adminpage_ip3: String,
+
+    #[serde(rename = "password")]
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Auth {
+    #[serde(rename = "auth_server")]
+    pub auth_server: String,
+
+    #[serde(rename = "traffic_profile")]
+    pub traffic_profile: i64,
+
+    #[serde(rename = "ports")]
+    pub ports: Vec<Port>,
+
+    #[serde(rename = "extra", default)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Port {
+    #[serde(rename = "port", default)]
+    pub port: Option<i64>,
+
+    #[serde(rename = "p2p_port", default)]
+    pub p2p_port: Option<i64>,
+
+    #[serde(rename = "extra", default)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Channels {
+    #[serde(rename = "common_maps", default)]
+    pub common_maps: Vec<Part>,
+
+    /// Maps split automatically into `common_maps`, replacing the parts listed there
+    #[serde(rename = "balance", default)]
+    pub balance: Option<MapBalance>,
+
+    #[serde(rename = "settings")]
+    pub settings: Vec<Setting>,
+
+    /// Runs of similar channels, expanded into `settings` when the config is read
+    #[serde(rename = "ranges", default)]
+    pub ranges: Vec<ChannelRange>,
+
+    /// Distance between the ports of consecutive parts of a channel
+    #[serde(rename = "port_stride", default = "default_port_stride")]
+    pub port_stride: i64,
+}
+
+fn default_port_stride() -> i64 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MapBalance {
+    /// Number of parts the maps are split into
+    #[serde(rename = "parts")]
+    pub parts: usize,
+
+    #[serde(rename = "maps")]
+    pub maps: Vec<WeightedMap>,
+}
+
+/// Map index with its expected load. Accepted either as a plain index or as an object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "WeightedMapDefinition")]
+pub struct WeightedMap {
+    #[serde(rename = "map")]
+    pub map: i64,
+
+    #[serde(rename = "weight")]
+    pub weight: i64,
+
+    /// Part the map is kept on, starting at 1
+    #[serde(rename = "part")]
+    pub part: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WeightedMapDefinition {
+    Index(i64),
+    Object {
+        #[serde(rename = "map")]
+        map: i64,
+
+        #[serde(rename = "weight", default = "default_map_weight")]
+        weight: i64,
+
+        #[serde(rename = "part", default)]
+        part: Option<usize>,
+    },
+}
+
+fn default_map_weight() -> i64 {
+    1
+}
+
+impl From<WeightedMapDefinition> for WeightedMap {
+    fn from(definition: WeightedMapDefinition) -> Self {
+        match definition {
+            WeightedMapDefinition::Index(map) => WeightedMap {
+                map,
+                weight: default_map_weight(),
+                part: None,
+            },
+            WeightedMapDefinition::Object { map, weight, part } => {
+                WeightedMap { map, weight, part }
+            }
+        }
+    }
+}
+
+/// Channels `from..=to` sharing everything except `channel_id` and ports. Channels also
+/// declared in `settings` are skipped.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelRange {
+    #[serde(rename = "from")]
+    pub from: i64,
+
+    #[serde(rename = "to")]
+    pub to: i64,
+
+    /// Port of channel `from`, unset to allocate from `port_ranges`
+    #[serde(rename = "port", default)]
+    pub port: Option<i64>,
+
+    #[serde(rename = "port_step", default = "default_channel_step")]
+    pub port_step: i64,
+
+    #[serde(rename = "p2p_port", default)]
+    pub p2p_port: Option<i64>,
+
+    #[serde(rename = "p2p_port_step", default = "default_channel_step")]
+    pub p2p_port_step: i64,
+
+    #[serde(rename = "port_stride", default)]
+    pub port_stride: Option<i64>,
+
+    #[serde(rename = "override_maps", default)]
+    pub override_maps: Option<Vec<Part>>,
+
+    #[serde(rename = "extra", default)]
+    pub extra: Extra,
+
+    #[serde(rename = "common", default)]
+    pub common: Option<CommonOverride>,
+}
+
+fn default_channel_step() -> i64 {
+    100
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Setting {
+    #[serde(rename = "rename")]
+    pub rename: Option<String>,
+
+    #[serde(rename = "channel_id")]
+    pub channel_id: i64,
+
+    /// Port of part 1, unset to allocate every part from `port_ranges`
+    #[serde(rename = "port", default)]
+    pub port: Option<i64>,
+
+    #[serde(rename = "p2p_port", default)]
+    pub p2p_port: Option<i64>,
+
+    /// `channels.port_stride` for this channel only
+    #[serde(rename = "port_stride", default)]
+    pub port_stride: Option<i64>,
+
+    #[serde(rename = "override_maps")]
+    pub override_maps: Option<Vec<Part>>,
+
+    #[serde(rename = "extra", default)]
+    pub extra: Extra,
+
+    #[serde(rename = "common", default)]
+    pub common: Option<CommonOverride>,
+
+    /// Index into `channels.ranges` of the range this setting was expanded from
+    #[serde(skip)]
+    pub range: Option<usize>,
+}
+
+/// One core process of a channel. Accepted either as a plain list of map indexes
+/// (`[1, 2, 3]`) or as an object with the list under `maps`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "PartDefinition")]
+pub struct Part {
+    #[serde(rename = "maps")]
+    pub maps: Vec<i64>,
+
+    /// `HOSTNAME` in CONFIG, `part<n>` when unset
+    #[serde(rename = "hostname")]
+    pub hostname: Option<String>,
+
+    #[serde(rename = "port")]
+    pub port: Option<i64>,
+
+    #[serde(rename = "p2p_port")]
+    pub p2p_port: Option<i64>,
+
+    /// Name of the binary symlink, `rename` or `game<channel>_<part>` when unset
+    #[serde(rename = "binary")]
+    pub binary: Option<String>,
+
+    #[serde(rename = "extra")]
+    pub extra: Extra,
+
+    #[serde(rename = "common")]
+    pub common: Option<CommonOverride>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PartDefinition {
+    Maps(Vec<i64>),
+    Object(Box<PartObject>),
+}
+
+#[derive(Deserialize)]
+struct PartObject {
+    #[serde(rename = "maps", default)]
+    maps: Vec<i64>,
+
+    #[serde(rename = "hostname", default)]
+    hostname: Option<String>,
+
+    #[serde(rename = "port", default)]
+    port: Option<i64>,
+
+    #[serde(rename = "p2p_port", default)]
+    p2p_port: Option<i64>,
+
+    #[serde(rename = "binary", default)]
+    binary: Option<String>,
+
+    #[serde(rename = "extra", default)]
+    extra: Extra,
+
+    #[serde(rename = "common", default)]
+    common: Option<CommonOverride>,
+}
+
+impl From<PartDefinition> for Part {
+    fn from(definition: PartDefinition) -> Self {
+        match definition {
+            PartDefinition::Maps(maps) => Part {
+                maps,
+                ..Default::default()
+            },
+            PartDefinition::Object(o) => {
+                let o = *o;
+                Part {
+                    maps: o.maps,
+                    hostname: o.hostname,
+                    port: o.port,
+                    p2p_port: o.p2p_port,
+                    binary: o.binary,
+                    extra: o.extra,
+                    common: o.common,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Common {
+    #[serde(rename = "table_postfix")]
+    pub table_postfix: String,
+
+    #[serde(rename = "passes_per_sec")]
+    pub passes_per_sec: i64,
+
+    #[serde(rename = "db_ip")]
+    pub db_ip: String,
+
+    #[serde(rename = "db_port")]
+    pub db_port: i64,
+
+    #[serde(rename = "save_event_second_cycle")]
+    pub save_event_second_cycle: i64,
+
+    #[serde(rename = "ping_event_second_cycle")]
+    pub ping_event_second_cycle: i64,
+
+    #[serde(rename = "view_range")]
+    pub view_range: i64,
+
+    #[serde(rename = "locale_service")]
+    pub locale_service: String,
+
+    #[serde(rename = "speedhack_limit_count")]
+    pub speedhack_limit_count: i64,
+
+    #[serde(rename = "speedhack_limit_bonus")]
+    pub speedhack_limit_bonus: i64,
+
+    #[serde(rename = "pk_protect_level")]
+    pub pk_protect_level: i64,
+
+    #[serde(rename = "mall_url")]
+    pub mall_url: String,
+
+    #[serde(rename = "traffic_profile")]
+    pub traffic_profile: i64,
+
+    #[serde(rename = "test_server")]
+    pub test_server: i64,
+
+    #[serde(rename = "max_level")]
+    pub max_level: i64,
+
+    #[serde(rename = "disable_item_bonus_change_time")]
+    pub disable_item_bonus_change_time: i64,
+
+    #[serde(rename = "extra", default)]
+    pub extra: Extra,
+}
+
+/// `Common` fields a channel or a single part sets differently, unset ones are inherited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommonOverride {
+    #[serde(rename = "table_postfix", default)]
+    pub table_postfix: Option<String>,
+
+    #[serde(rename = "passes_per_sec", default)]
+    pub passes_per_sec: Option<i64>,
+
+    #[serde(rename = "db_ip", default)]
+    pub db_ip: Option<String>,
+
+    #[serde(rename = "db_port", default)]
+    pub db_port: Option<i64>,
+
+    #[serde(rename = "save_event_second_cycle", default)]
+    pub save_event_second_cycle: Option<i64>,
+
+    #[serde(rename = "ping_event_second_cycle", default)]
+    pub ping_event_second_cycle: Option<i64>,
+
+    #[serde(rename = "view_range", default)]
+    pub view_range: Option<i64>,
+
+    #[serde(rename = "locale_service", default)]
+    pub locale_service: Option<String>,
+
+    #[serde(rename = "speedhack_limit_count", default)]
+    pub speedhack_limit_count: Option<i64>,
+
+    #[serde(rename = "speedhack_limit_bonus", default)]
+    pub speedhack_limit_bonus: Option<i64>,
+
+    #[serde(rename = "pk_protect_level", default)]
+    pub pk_protect_level: Option<i64>,
+
+    #[serde(rename = "mall_url", default)]
+    pub mall_url: Option<String>,
+
+    #[serde(rename = "traffic_profile", default)]
+    pub traffic_profile: Option<i64>,
+
+    #[serde(rename = "test_server", default)]
+    pub test_server: Option<i64>,
+
+    #[serde(rename = "max_level", default)]
+    pub max_level: Option<i64>,
+
+    #[serde(rename = "disable_item_bonus_change_time", default)]
+    pub disable_item_bonus_change_time: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Databases {
+    #[serde(rename = "player")]
+    pub player: Database,
+
+    #[serde(rename = "common")]
+    pub common: Database,
+
+    #[serde(rename = "hotbackup")]
+    pub hotbackup: Database,
+
+    #[serde(rename = "log")]
+    pub log: Database,
+
+    #[serde(rename = "account")]
+    pub account: Database,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Database {
+    #[serde(rename = "ip")]
+    pub ip: String,
+
+    #[serde(rename = "port")]
+    pub port: String,
+
+    #[serde(rename = "database")]
+    pub database: String,
+
+    #[serde(rename = "user")]
+    pub user: String,
+
+    #[serde(rename = "password")]
+    pub password: String,
+
+    #[serde(rename = "sock")]
+    pub sock: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Db {
+    #[serde(rename = "bind_port")]
+    pub bind_port: i64,
+
+    #[serde(rename = "db_sleep_msec")]
+    pub db_sleep_msec: i64,
+
+    #[serde(rename = "client_heart_fps")]
+    pub client_heart_fps: i64,
+
+    #[serde(rename = "hash_player_life_sec")]
+    pub hash_player_life_sec: i64,
+
+    #[serde(rename = "player_delete_level_limit")]
+    pub player_delete_level_limit: i64,
+
+    #[serde(rename = "player_id_start")]
+    pub player_id_start: i64,
+
+    #[serde(rename = "item_id_range")]
+    pub item_id_range: ItemIdRange,
+
+    #[serde(rename = "test_server")]
+    pub test_server: i64,
+
+    #[serde(rename = "extra", default)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ItemIdRange {
+    #[serde(rename = "start")]
+    pub start: i64,
+
+    #[serde(rename = "end")]
+    pub end: i64,
+}
+
+impl Common {
+    /// Copy of `self` with every field set in `o` replaced.
+    pub fn merged(&self, o: &CommonOverride) -> Common {
+        Common {
+            table_postfix: o
+                .table_postfix
+                .clone()
+                .unwrap_or_else(|| self.table_postfix.clone()),
+            passes_per_sec: o.passes_per_sec.unwrap_or(self.passes_per_sec),
+            db_ip: o.db_ip.clone().unwrap_or_else(|| self.db_ip.clone()),
+            db_port: o.db_port.unwrap_or(self.db_port),
+            save_event_second_cycle: o
+                .save_event_second_cycle
+                .unwrap_or(self.save_event_second_cycle),
+            ping_event_second_cycle: o
+                .ping_event_second_cycle
+                .unwrap_or(self.ping_event_second_cycle),
+            view_range: o.view_range.unwrap_or(self.view_range),
+            locale_service: o
+                .locale_service
+                .clone()
+                .unwrap_or_else(|| self.locale_service.clone()),
+            speedhack_limit_count: o
+                .speedhack_limit_count
+                .unwrap_or(self.speedhack_limit_count),
+            speedhack_limit_bonus: o
+                .speedhack_limit_bonus
+                .unwrap_or(self.speedhack_limit_bonus),
+            pk_protect_level: o.pk_protect_level.unwrap_or(self.pk_protect_level),
+            mall_url: o.mall_url.clone().unwrap_or_else(|| self.mall_url.clone()),
+            traffic_profile: o.traffic_profile.unwrap_or(self.traffic_profile),
+            test_server: o.test_server.unwrap_or(self.test_server),
+            max_level: o.max_level.unwrap_or(self.max_level),
+            disable_item_bonus_change_time: o
+                .disable_item_bonus_change_time
+                .unwrap_or(self.disable_item_bonus_change_time),
+            extra: self.extra.clone(),
+        }
+    }
+}
+
+/// Renders a scalar extra value the way the cores expect it, booleans as 0/1.
+/// Arrays, objects, null and strings spanning several lines cannot be written and yield `None`.
+pub fn extra_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(v) if v.contains(['\n', '\r']) => None,
+        Value::String(v) => Some(v.clone()),
+        Value::Number(v) => Some(v.to_string()),
+        Value::Bool(v) => Some((*v as i64).to_string()),
+        _ => None,
+    }
+}
+
+/// Merges extra layers, later ones override keys of earlier ones, into `rendered`. A key the
+/// template already has replaces the value of that line, the others are appended as lines
+/// joined with `separator`.
+pub fn merge_extra(rendered: &str, layers: &[&Extra], separator: &str) -> String {
+    let mut merged: BTreeMap<&str, String> = BTreeMap::new();
+    for layer in layers.iter() {
+        for (k, v) in layer.iter() {
+            if let Some(v) = extra_value(v) {
+                merged.insert(k, v);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for line in rendered.lines() {
+        let value = match line.split_once(separator.trim()) {
+            Some((key, _)) => merged.remove(key.trim()).map(|v| (key.trim(), v)),
+            None => None,
+        };
+        match value {
+            Some((key, v)) => out.push_str(&format!("{}{}{}", key, separator, v)),
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    if !rendered.ends_with('\n') && merged.is_empty() {
+        out.pop();
+    }
+
+    for (k, v) in merged {
+        out.push_str(&format!("{}{}{}\n", k, separator, v));
+    }
+
+    out
+}
+
+impl Database {
+    /// Connection string in the `ip user password database port sock` form the cores expect.
+    pub fn sql(&self) -> String {
+        format!(
+            "{} {} {} {} {} {}",
+            self.ip, self.user, self.password, self.database, self.port, self.sock
+        )
+    }
+}
+
+impl Channels {
+    /// Appends a `Setting` for every channel of `ranges` that is not in `settings` yet.
+    pub fn expand_ranges(&mut self) {
+        let explicit: Vec<i64> = self.settings.iter().map(|s| s.channel_id).collect();
+
+        for (range, r) in self.ranges.iter().enumerate() {
+            for (i, channel_id) in (r.from..=r.to).enumerate() {
+                if explicit.contains(&channel_id) {
+                    continue;
+                }
+
+                let i = i as i64;
+                self.settings.push(Setting {
+                    rename: None,
+                    channel_id,
+                    port: r.port.map(|p| p + i * r.port_step),
+                    p2p_port: r.p2p_port.map(|p| p + i * r.p2p_port_step),
+                    port_stride: r.port_stride,
+                    override_maps: r.override_maps.clone(),
+                    extra: r.extra.clone(),
+                    common: r.common.clone(),
+                    range: Some(range),
+                });
+            }
+        }
+    }
+}
+
+impl Setting {
+    pub fn channel_dir_name(&self) -> Cow<'_, str> {
+        match self.rename {
+            Some(ref v) => Cow::Borrowed(v),
+            None => Cow::Owned(format!("channel{}", self.channel_id)),
+        }
+    }
+
+    pub fn get_parts<'a>(&'a self, channels: &'a Channels) -> &'a Vec<Part> {
+        match self.override_maps {
+            Some(ref v) => v,
+            None => &channels.common_maps,
+        }
+    }
+}
+
+/// Port of a single part and the config path it was taken from.
+#[derive(Debug)]
+pub struct ResolvedPort {
+    pub value: i64,
+    pub path: String,
+}
+
+impl Config {
+    /// JSON path of `field` (empty for the whole object) of `channels.settings[setting]`, followed
+    /// by `detail` in parentheses. Channels expanded from `channels.ranges` point at their range
+    /// and name the channel in the details.
+    pub fn setting_path(&self, setting: usize, field: &str, detail: Option<String>) -> String {
+        let x = &self.channels.settings[setting];
+        let (mut path, mut details) = match x.range {
+            Some(r) => (
+                format!("channels.ranges[{}]", r),
+                vec![format!("channel {}", x.channel_id)],
+            ),
+            None => (format!("channels.settings[{}]", setting), vec![]),
+        };
+        if !field.is_empty() {
+            path.push('.');
+            path.push_str(field);
+        }
+        details.extend(detail);
+        if !details.is_empty() {
+            path.push_str(&format!(" ({})", details.join(", ")));
+        }
+
+        path
+    }
+
+    /// JSON path of `field` (empty for the whole object) of the definition of part `part`
+    /// (starting at 1) of `channels.settings[setting]`.
+    pub fn part_path(&self, setting: usize, part: usize, field: &str) -> String {
+        let field = match field {
+            "" => String::new(),
+            v => format!(".{}", v),
+        };
+        match self.channels.settings[setting].override_maps {
+            Some(_) => self.setting_path(
+                setting,
+                &format!("override_maps[{}]{}", part - 1, field),
+                None,
+            ),
+            None => format!("channels.common_maps[{}]{}", part - 1, field),
+        }
+    }
+
+    /// Ports of part `part` (starting at 1) of `channels.settings[setting]` given in the config:
+    /// the part definition, otherwise the channel ports shifted by `(part - 1) * port_stride`.
+    /// `None` when the port has to be allocated from `port_ranges`.
+    pub fn explicit_part_ports(
+        &self,
+        setting: usize,
+        part: usize,
+    ) -> (Option<ResolvedPort>, Option<ResolvedPort>) {
+        let x = &self.channels.settings[setting];
+        let definition = &x.get_parts(&self.channels)[part - 1];
+        // saturated, an offset out of range is reported by validation instead of overflowing
+        let offset =
+            (part as i64 - 1).saturating_mul(x.port_stride.unwrap_or(self.channels.port_stride));
+
+        let resolve = |name: &str, explicit: Option<i64>, base: Option<i64>| match (explicit, base)
+        {
+            (Some(value), _) => Some(ResolvedPort {
+                value,
+                path: self.part_path(setting, part, name),
+            }),
+            (None, Some(base)) => Some(ResolvedPort {
+                value: base.saturating_add(offset),
+                path: self.setting_path(setting, name, Some(format!("part {}", part))),
+            }),
+            (None, None) => None,
+        };
+
+        (
+            resolve("port", definition.port, x.port),
+            resolve("p2p_port", definition.p2p_port, x.p2p_port),
+        )
+    }
+
+    /// Ports of auth `id` (starting at 1) given in the config.
+    pub fn explicit_auth_ports(&self, id: usize) -> (Option<ResolvedPort>, Option<ResolvedPort>) {
+        let ports = &self.auth.ports[id - 1];
+        let resolve = |name: &str, value: Option<i64>| {
+            value.map(|value| ResolvedPort {
+                value,
+                path: format!("auth.ports[{}].{}", id - 1, name),
+            })
+        };
+
+        (
+            resolve("port", ports.port),
+            resolve("p2p_port", ports.p2p_port),
+        )
+    }
+
+    /// Listening and P2P port of a part, taken from `allocated_ports` when the config has none.
+    pub fn part_ports(&self, setting: usize, part: usize) -> (ResolvedPort, ResolvedPort) {
+        let key = part_port_key(self.channels.settings[setting].channel_id, part);
+        let (port, p2p_port) = self.explicit_part_ports(setting, part);
+
+        (
+            port.unwrap_or_else(|| self.allocated_port(&key, "game", |a| a.port)),
+            p2p_port.unwrap_or_else(|| self.allocated_port(&key, "p2p", |a| a.p2p_port)),
+        )
+    }
+
+    /// Listening and P2P port of auth `id`, taken from `allocated_ports` when the config has none.
+    pub fn auth_ports(&self, id: usize) -> (ResolvedPort, ResolvedPort) {
+        let key = auth_port_key(id);
+        let (port, p2p_port) = self.explicit_auth_ports(id);
+
+        (
+            port.unwrap_or_else(|| self.allocated_port(&key, "auth", |a| a.port)),
+            p2p_port.unwrap_or_else(|| self.allocated_port(&key, "p2p", |a| a.p2p_port)),
+        )
+    }
+
+    fn allocated_port(
+        &self,
+        key: &str,
+        range: &str,
+        field: impl Fn(&AllocatedPorts) -> i64,
+    ) -> ResolvedPort {
+        ResolvedPort {
+            // 0 is rejected by validation if allocation was skipped
+            value: self.allocated_ports.get(key).map_or(0, field),
+            path: format!("port_ranges.{} ({})", range, key),
+        }
+    }
+
+    /// `common` as seen by one part: global values, then the channel's and finally the part
+    /// definition's.
+    pub fn part_common(&self, setting: &Setting, part: usize) -> Common {
+        let mut common = self.common.clone();
+        if let Some(ref o) = setting.common {
+            common = common.merged(o);
+        }
+        if let Some(ref o) = setting.get_parts(&self.channels)[part - 1].common {
+            common = common.merged(o);
+        }
+
+        common
+    }
+
+    pub fn game_binary(&self) -> String {
+        match self.share.game_binary {
+            Some(ref v) => v.clone(),
+            None => format!("game_{}", self.server_name.to_lowercase()),
+        }
+    }
+
+    /// Directory the tree lives in on the game server, used by scripts and units.
+    pub fn home_dir(&self) -> String {
+        match self.install_dir {
+            Some(ref v) => v.display().to_string(),
+            None => format!("/home/{}", self.server_name),
+        }
+    }
+
+    pub fn read_config(file: &Path) -> ConfigResult<Config> {
+        if !file.exists() {
+            return Err(ConfigError::NotFound {
+                path: file.to_path_buf(),
+            });
+        }
+
+        let data = read_to_string(file).context(Read)?;
+        Self::parse(&data)
+    }
+
+    /// Parses a config and expands `channels.balance` and `channels.ranges`.
+    pub fn parse(data: &str) -> ConfigResult<Config> {
+        let mut config = serde_json::from_str::<Config>(data).context(Parse)?;
+        if let Some(ref b) = config.channels.balance {
+            config.channels.common_maps = b
+                .split()
+                .into_iter()
+                .map(|maps| Part {
+                    maps,
+                    ..Default::default()
+                })
+                .collect();
+        }
+        config.channels.expand_ranges();
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::{self, create_dir, hard_link, read_dir};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a file, or of the relative paths and contents of everything below a directory.
+/// Symlinks are followed, like `copy` does.
+pub fn checksum(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hash(path, Path::new(""), &mut hasher)?;
+
+    Ok(hex(&hasher.finalize()))
+}
+
+/// SHA-256 of `bytes`, hex encoded like `checksum`.
+pub fn sha256(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `path` is the entry itself, `rel` its path below the one `checksum` was called with.
+fn hash(path: &Path, rel: &Path, hasher: &mut Sha256) -> io::Result<()> {
+    if fs::metadata(path)?.is_dir() {
+        hasher.update(format!("d {}\n", rel.display()));
+        for name in sorted_names(path)? {
+            hash(&path.join(&name), &rel.join(&name), hasher)?;
+        }
+    } else {
+        let contents = fs::read(path)?;
+        hasher.update(format!("f {} {}\n", rel.display(), contents.len()));
+        hasher.update(contents);
+    }
+
+    Ok(())
+}
+
+fn sorted_names(dir: &Path) -> io::Result<Vec<std::ffi::OsString>> {
+    let mut names = read_dir(dir)?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+
+    Ok(names)
+}
+
+/// Copies a file or a whole directory, keeping permissions.
+pub fn copy(source: &Path, dest: &Path) -> io::Result<()> {
+    if fs::metadata(source)?.is_dir() {
+        create_dir(dest)?;
+        for name in sorted_names(source)? {
+            copy(&source.join(&name), &dest.join(&name))?;
+        }
+    } else {
+        fs::copy(source, dest)?;
+    }
+
+    Ok(())
+}
+
+/// Hard links a file, directories are recreated with every file inside linked.
+pub fn hardlink(source: &Path, dest: &Path) -> io::Result<()> {
+    if fs::metadata(source)?.is_dir() {
+        create_dir(dest)?;
+        for name in sorted_names(source)? {
+            hardlink(&source.join(&name), &dest.join(&name))?;
+        }
+    } else {
+        hard_link(source, dest)?;
+    }
+
+    Ok(())
+}
+
+/// Whether `dest` is what `hardlink(source, dest)` creates: the same files, linked.
+pub fn is_hardlinked(source: &Path, dest: &Path) -> io::Result<bool> {
+    let (s, d) = (fs::metadata(source)?, fs::symlink_metadata(dest)?);
+
+    if s.is_dir() {
+        if !d.is_dir() || sorted_names(source)? != sorted_names(dest)? {
+            return Ok(false);
+        }
+        for name in sorted_names(source)? {
+            if !is_hardlinked(&source.join(&name), &dest.join(&name))? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    Ok(d.is_file() && s.dev() == d.dev() && s.ino() == d.ino())
+}
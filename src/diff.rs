@@ -0,0 +1,129 @@
+use std::fmt::Write;
+
+/// Lines of unchanged context around every change
+const CONTEXT: usize = 3;
+
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Shortest edit script turning `old` into `new`, from a longest common subsequence.
+fn ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    // lcs[i][j]: longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Equal);
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+
+    ops
+}
+
+/// Start of a hunk in `diff -u` notation: 1-based, or the line before it when it is empty.
+fn start(first: usize, count: usize) -> usize {
+    if count == 0 {
+        first
+    } else {
+        first + 1
+    }
+}
+
+/// Unified diff from `old` to `new` with `CONTEXT` lines around every change, empty when
+/// they are equal.
+pub fn unified(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let ops = ops(&old, &new);
+
+    // (old line, new line) every op starts at
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut i, mut j) = (0, 0);
+    for op in ops.iter() {
+        positions.push((i, j));
+        match op {
+            Op::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Op::Delete => i += 1,
+            Op::Insert => j += 1,
+        }
+    }
+
+    // ranges of ops shown together, changes closer than twice the context share a hunk
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (n, op) in ops.iter().enumerate() {
+        if let Op::Equal = op {
+            continue;
+        }
+        let (from, to) = (n.saturating_sub(CONTEXT), (n + CONTEXT + 1).min(ops.len()));
+        match hunks.last_mut() {
+            Some(last) if from <= last.1 => last.1 = to,
+            _ => hunks.push((from, to)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (from, to) in hunks {
+        let ops = &ops[from..to];
+        let (i, j) = positions[from];
+        let old_count = ops.iter().filter(|o| !matches!(o, Op::Insert)).count();
+        let new_count = ops.iter().filter(|o| !matches!(o, Op::Delete)).count();
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            start(i, old_count),
+            old_count,
+            start(j, new_count),
+            new_count
+        )
+        .unwrap();
+
+        let (mut i, mut j) = (i, j);
+        for op in ops {
+            match op {
+                Op::Equal => {
+                    writeln!(out, " {}", old[i]).unwrap();
+                    i += 1;
+                    j += 1;
+                }
+                Op::Delete => {
+                    writeln!(out, "-{}", old[i]).unwrap();
+                    i += 1;
+                }
+                Op::Insert => {
+                    writeln!(out, "+{}", new[j]).unwrap();
+                    j += 1;
+                }
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,471 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::deploy;
+
+/// What a path is once symlinks are followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Directory,
+    File,
+    /// Regular file with an executable bit set
+    Executable,
+}
+
+/// Everything the maker reads from or does to the server directory goes through this, so the
+/// tree can be generated on disk with `RealFileSystem` or in memory with `MemoryFileSystem`.
+pub trait FileSystem: fmt::Debug {
+    /// Names in `dir` with their kind, `None` for dangling symlinks.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(OsString, Option<FileKind>)>>;
+    fn kind(&self, path: &Path) -> io::Result<FileKind>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    /// Target of the symlink at `path`, fails for anything else.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()>;
+    /// Hard links a file, or every file below a directory.
+    fn hardlink(&self, source: &Path, path: &Path) -> io::Result<()>;
+    /// Copies a file or a directory, failing if the copy does not match the source.
+    fn copy(&self, source: &Path, path: &Path) -> io::Result<()>;
+    /// Whether `path` is a hard link of `source`, or every file below it of the one in `source`.
+    fn is_hardlinked(&self, source: &Path, path: &Path) -> io::Result<bool>;
+    /// SHA-256 of a file, or of the names and contents of everything below a directory.
+    fn checksum(&self, path: &Path) -> io::Result<String>;
+    /// Removes a file, symlink or whole directory. Symlinks are never followed.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(OsString, Option<FileKind>)>> {
+        fs::read_dir(dir)?
+            .map(|e| e.map(|e| (e.file_name(), self.kind(&e.path()).ok())))
+            .collect()
+    }
+
+    fn kind(&self, path: &Path) -> io::Result<FileKind> {
+        let meta = fs::metadata(path)?;
+        Ok(if meta.is_dir() {
+            FileKind::Directory
+        } else if meta.permissions().mode() & 0o111 != 0 {
+            FileKind::Executable
+        } else {
+            FileKind::File
+        })
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    fn hardlink(&self, source: &Path, path: &Path) -> io::Result<()> {
+        deploy::hardlink(source, path)
+    }
+
+    fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+        deploy::copy(source, path)?;
+
+        if deploy::checksum(source)? != deploy::checksum(path)? {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "copy does not match its source",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn is_hardlinked(&self, source: &Path, path: &Path) -> io::Result<bool> {
+        deploy::is_hardlinked(source, path)
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<String> {
+        deploy::checksum(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(path)?.file_type().is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Directory,
+    File {
+        contents: Vec<u8>,
+        executable: bool,
+    },
+    Symlink(PathBuf),
+    /// Hard link to the given path, sees every later change of it
+    Hardlink(PathBuf),
+}
+
+/// Tree kept in a map of absolute paths. Parents have to exist like on disk.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    nodes: RefCell<BTreeMap<PathBuf, Node>>,
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("{:?} does not exist", path))
+}
+
+/// Resolves `.` and `..` without touching any filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts `node` at `path`, creating missing parents. Meant for preparing `share/` in tests.
+    pub fn insert<P: AsRef<Path>>(&self, path: P, node: Node) {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.nodes.borrow_mut();
+        for parent in path.ancestors().skip(1) {
+            if parent.parent().is_some() {
+                nodes.entry(parent.to_path_buf()).or_insert(Node::Directory);
+            }
+        }
+        nodes.insert(path, node);
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<Node> {
+        self.nodes.borrow().get(&normalize(path.as_ref())).cloned()
+    }
+
+    /// Every node below `root`, keyed by the path relative to it.
+    pub fn tree<P: AsRef<Path>>(&self, root: P) -> BTreeMap<PathBuf, Node> {
+        let root = normalize(root.as_ref());
+        self.nodes
+            .borrow()
+            .iter()
+            .filter_map(|(p, n)| match p.strip_prefix(&root) {
+                Ok(rel) if !rel.as_os_str().is_empty() => Some((rel.to_path_buf(), n.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn add(&self, path: &Path, node: Node) -> io::Result<()> {
+        let path = normalize(path);
+        let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+        let mut nodes = self.nodes.borrow_mut();
+
+        if nodes.contains_key(&path) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{:?} already exists", path),
+            ));
+        }
+        match nodes.get(parent) {
+            Some(Node::Directory) => {}
+            Some(_) => return Err(io::Error::other(format!("{:?} is not a directory", parent))),
+            None if parent.parent().is_none() => {}
+            None => return Err(not_found(parent)),
+        }
+        nodes.insert(path, node);
+
+        Ok(())
+    }
+
+    /// The node at `path` with symlinks and hard links followed to their source.
+    fn resolve(&self, path: &Path, depth: usize) -> io::Result<Node> {
+        let path = self.resolve_path(path, depth)?;
+        self.get(&path).ok_or_else(|| not_found(&path))
+    }
+
+    /// Path of the node `resolve` ends up at.
+    fn resolve_path(&self, path: &Path, depth: usize) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        let node = self.get(&path).ok_or_else(|| not_found(&path))?;
+        if depth > 16 {
+            return Err(io::Error::other("too many levels of links"));
+        }
+
+        match node {
+            Node::Symlink(target) => {
+                let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+                self.resolve_path(&parent.join(target), depth + 1)
+            }
+            Node::Hardlink(source) => self.resolve_path(&source, depth + 1),
+            _ => Ok(path),
+        }
+    }
+
+    /// Appends what `deploy::checksum` hashes for `path` to `out`, `rel` is its path below the
+    /// one the checksum is for.
+    fn hashed(&self, path: &Path, rel: &Path, out: &mut Vec<u8>) -> io::Result<()> {
+        let resolved = self.resolve_path(path, 0)?;
+        if self.kind(&resolved)? == FileKind::Directory {
+            out.extend(format!("d {}\n", rel.display()).bytes());
+            let mut names = self.read_dir(&resolved)?;
+            names.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, _) in names {
+                self.hashed(&resolved.join(&name), &rel.join(&name), out)?;
+            }
+        } else {
+            let contents = self.read(&resolved)?;
+            out.extend(format!("f {} {}\n", rel.display(), contents.len()).bytes());
+            out.extend(contents);
+        }
+
+        Ok(())
+    }
+
+    fn take(&self, path: &Path) -> io::Result<Vec<(PathBuf, Node)>> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.borrow_mut();
+        if !nodes.contains_key(&path) {
+            return Err(not_found(&path));
+        }
+
+        let below = nodes
+            .keys()
+            .filter(|p| p.starts_with(&path))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Ok(below
+            .into_iter()
+            .map(|p| {
+                let n = nodes.remove(&p).unwrap();
+                (p, n)
+            })
+            .collect())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(OsString, Option<FileKind>)>> {
+        let dir = normalize(dir);
+        let names = self
+            .nodes
+            .borrow()
+            .keys()
+            .filter(|p| p.parent() == Some(&dir))
+            .map(|p| p.to_path_buf())
+            .collect::<Vec<_>>();
+
+        Ok(names
+            .into_iter()
+            .map(|p| (p.file_name().unwrap().to_os_string(), self.kind(&p).ok()))
+            .collect())
+    }
+
+    fn kind(&self, path: &Path) -> io::Result<FileKind> {
+        Ok(match self.resolve(path, 0)? {
+            Node::Directory => FileKind::Directory,
+            Node::File {
+                executable: true, ..
+            } => FileKind::Executable,
+            _ => FileKind::File,
+        })
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.resolve(path, 0)? {
+            Node::File { contents, .. } => Ok(contents),
+            _ => Err(io::Error::other(format!("{:?} is not a file", path))),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.get(path) {
+            Some(Node::Symlink(target)) => Ok(target),
+            Some(_) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} is not a symlink", path),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.add(path, Node::Directory)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.insert(path, Node::Directory);
+        Ok(())
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let node = Node::File {
+            contents: contents.to_vec(),
+            executable: false,
+        };
+        if let Some(Node::File { .. }) = self.get(path) {
+            self.nodes.borrow_mut().insert(normalize(path), node);
+            return Ok(());
+        }
+        self.add(path, node)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        self.add(path, Node::Symlink(target.to_path_buf()))
+    }
+
+    fn hardlink(&self, source: &Path, path: &Path) -> io::Result<()> {
+        self.kind(source)?;
+        self.add(path, Node::Hardlink(normalize(source)))
+    }
+
+    /// Copies the nodes below `source` with links followed, later changes of the source are
+    /// not seen by the copy.
+    fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+        let source = self.resolve_path(source, 0)?;
+        let node = self.resolve(&source, 0)?;
+        let is_dir = node == Node::Directory;
+        self.add(path, node)?;
+
+        if is_dir {
+            for (name, _) in self.read_dir(&source)? {
+                self.copy(&source.join(&name), &path.join(&name))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_hardlinked(&self, source: &Path, path: &Path) -> io::Result<bool> {
+        self.kind(source)?;
+        Ok(self.get(path) == Some(Node::Hardlink(normalize(source))))
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<String> {
+        let mut hashed = vec![];
+        self.hashed(path, Path::new(""), &mut hashed)?;
+
+        Ok(deploy::sha256(&hashed))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.take(path).map(|_| ())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let moved = self.take(&from)?;
+
+        let mut nodes = self.nodes.borrow_mut();
+        for (p, n) in moved.into_iter() {
+            nodes.insert(to.join(p.strip_prefix(&from).unwrap()), n);
+        }
+
+        Ok(())
+    }
+}
+
+/// Lets a caller keep a handle on the file system it gives to the maker, e.g. to inspect a
+/// `MemoryFileSystem` afterwards.
+impl<T: FileSystem + ?Sized> FileSystem for Rc<T> {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(OsString, Option<FileKind>)>> {
+        (**self).read_dir(dir)
+    }
+
+    fn kind(&self, path: &Path) -> io::Result<FileKind> {
+        (**self).kind(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        (**self).read(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        (**self).read_link(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        (**self).create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        (**self).create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        (**self).write(path, contents)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        (**self).symlink(target, path)
+    }
+
+    fn hardlink(&self, source: &Path, path: &Path) -> io::Result<()> {
+        (**self).hardlink(source, path)
+    }
+
+    fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+        (**self).copy(source, path)
+    }
+
+    fn is_hardlinked(&self, source: &Path, path: &Path) -> io::Result<bool> {
+        (**self).is_hardlinked(source, path)
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<String> {
+        (**self).checksum(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        (**self).remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        (**self).rename(from, to)
+    }
+}
@@ -0,0 +1,30 @@
+//! Generates the directory tree of a server (db, channel parts, auth) from a single config.
+//!
+//! `Config` is read and validated, `Maker::layout` plans the tree and `Maker::make` writes it
+//! through a `FileSystem`, either the real one or `MemoryFileSystem`.
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod balance;
+pub mod config;
+pub mod deploy;
+pub mod diff;
+pub mod filesystem;
+pub mod maker;
+pub mod manifest;
+pub mod plan;
+pub mod ports;
+pub mod process;
+pub mod reconcile;
+pub mod supervisor;
+pub mod systemd;
+pub mod template;
+pub mod validation;
+
+pub use config::Config;
+pub use filesystem::{FileSystem, MemoryFileSystem, RealFileSystem};
+pub use maker::{Cleanup, Maker};
+pub use plan::Plan;
+
+pub const CONFIG_FILE: &str = "config.json";
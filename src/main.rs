@@ -1,41 +1,235 @@
-#[macro_use]
-extern crate lazy_static;
-
-use clap::{AppSettings, Clap};
-
-use crate::config::Config;
-use crate::maker::Maker;
-
-mod config;
-mod maker;
-
-#[derive(Clap)]
-#[clap(setting = AppSettings::ColoredHelp)]
-struct Opts {
-    #[clap(
-        short,
-        long,
-        about = "Delete directories and files that are not whitelisted"
-    )]
-    force: bool,
-}
-
-pub(crate) const CONFIG_FILE: &'static str = "config.json";
-
-fn main() {
-    let opts: Opts = Opts::parse();
-    let config = match Config::read_config() {
-        Ok(v) => v,
-        Err(err) => return println!("Error: {}", err.to_string()),
-    };
-    let maker = match Maker::new(config) {
-        Ok(v) => v,
-        Err(err) => return println!("Error: {}", err.to_string()),
-    };
-    match maker.check_current_directory(opts.force) {
-        Ok(v) => v,
-        Err(err) => return println!("Error: {}", err.to_string()),
-    };
-
-    maker.make().unwrap();
-}
+use std::fmt;
+use std::path::PathBuf;
+use std::process;
+
+use channels_maker::supervisor::Supervisor;
+use channels_maker::validation::validate;
+use channels_maker::{ports, systemd, Cleanup, Config, Maker, RealFileSystem, CONFIG_FILE};
+use clap::{AppSettings, Clap};
+
+#[derive(Clap)]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    #[clap(
+        short,
+        long,
+        global = true,
+        default_value = ".",
+        about = "Server directory the tree is generated in"
+    )]
+    root: PathBuf,
+    #[clap(
+        short,
+        long,
+        global = true,
+        about = "Path of the config file [default: <root>/config.json]"
+    )]
+    config: Option<PathBuf>,
+    #[clap(
+        short,
+        long,
+        global = true,
+        about = "Directory with templates overriding the built-in CONFIG and conf.txt [default: <root>/templates]"
+    )]
+    templates: Option<PathBuf>,
+    #[clap(
+        short,
+        long,
+        about = "Delete directories and files that are not whitelisted"
+    )]
+    force: bool,
+    #[clap(
+        short,
+        long,
+        about = "Like --force, but move old directories and files to backups/<timestamp> instead of deleting them"
+    )]
+    backup: bool,
+    #[clap(
+        long,
+        global = true,
+        about = "Print directories, symlinks and files that would be created or deleted without touching the disk"
+    )]
+    dry_run: bool,
+    #[clap(
+        long,
+        default_value = "tree",
+        possible_values = &["tree", "json"],
+        about = "Output format of --dry-run"
+    )]
+    format: String,
+    #[clap(subcommand)]
+    subcmd: Option<SubCommand>,
+}
+
+#[derive(Clap)]
+enum SubCommand {
+    #[clap(
+        about = "Create, update or remove only what differs from the config, keeping log and mark contents"
+    )]
+    Apply,
+    #[clap(
+        about = "Show how the tree on disk differs from what the config generates and exit with 1 if it does"
+    )]
+    Diff,
+    #[clap(
+        about = "Compare the tree with the manifest of the last run and exit with 1 on hand edits or missing entries"
+    )]
+    Verify,
+    #[clap(
+        about = "Start db, every channel part and auth in the foreground and restart them when they crash"
+    )]
+    Run,
+    #[clap(about = "Print the MAP_ALLOW line of every part split from channels.balance")]
+    Balance,
+    #[clap(about = "Print systemd units for db, every channel part and auth")]
+    Systemd {
+        #[clap(long, about = "Write the units to --unit-dir instead of printing them")]
+        install: bool,
+        #[clap(long, default_value = "/etc/systemd/system")]
+        unit_dir: PathBuf,
+    },
+}
+
+/// Exit status of `diff` and `verify` when the tree drifted.
+const EXIT_DRIFT: i32 = 1;
+/// Exit status of every failure, distinct from `EXIT_DRIFT` so scripts can tell them apart.
+const EXIT_ERROR: i32 = 2;
+
+/// Prints `err` to stderr and exits with `EXIT_ERROR`, so scripts and CI notice the failure.
+fn fail<E: fmt::Display>(err: E) -> ! {
+    eprintln!("Error: {}", err);
+    process::exit(EXIT_ERROR)
+}
+
+fn main() {
+    let opts: Opts = Opts::parse();
+    let config_path = match opts.config {
+        Some(ref v) => v.clone(),
+        None => opts.root.join(CONFIG_FILE),
+    };
+    let mut config = match Config::read_config(&config_path) {
+        Ok(v) => v,
+        Err(err) => fail(err),
+    };
+    if let Err(err) = ports::read_lock(&opts.root).and_then(|lock| config.allocate_ports(&lock)) {
+        fail(err);
+    }
+    let report = validate(&config, &RealFileSystem, &opts.root);
+    if !report.is_empty() {
+        eprintln!("{}", report);
+    }
+    if report.has_errors() {
+        fail("config is invalid, nothing was changed");
+    }
+    if let Some(SubCommand::Balance) = opts.subcmd {
+        let balance = match config.channels.balance {
+            Some(ref v) => v,
+            None => fail("channels.balance is not set"),
+        };
+        for (i, part) in config.channels.common_maps.iter().enumerate() {
+            let maps = part
+                .maps
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!(
+                "# part{} (load {})\nMAP_ALLOW: {}",
+                i + 1,
+                balance.load(&part.maps),
+                maps
+            );
+        }
+        return;
+    }
+    if let Some(SubCommand::Run) = opts.subcmd {
+        return match Supervisor::new(&config, &opts.root)
+            .and_then(|mut s| s.run(|e| println!("{}", e)))
+        {
+            Ok(_) => println!("all processes stopped"),
+            Err(err) => fail(err),
+        };
+    }
+    if let Some(SubCommand::Systemd {
+        install,
+        ref unit_dir,
+    }) = opts.subcmd
+    {
+        let units = systemd::units(&config);
+        if !install {
+            return units
+                .iter()
+                .for_each(|u| println!("# {}\n{}", u.name, u.contents));
+        }
+        return match systemd::install(&units, unit_dir) {
+            Ok(_) => println!(
+                "{} units written to {}, run systemctl daemon-reload",
+                units.len(),
+                unit_dir.display()
+            ),
+            Err(err) => fail(err),
+        };
+    }
+    let maker = match Maker::new(
+        config,
+        opts.root.clone(),
+        &config_path,
+        opts.templates.as_deref(),
+    ) {
+        Ok(v) => v,
+        Err(err) => fail(err),
+    };
+    if let Some(SubCommand::Diff) = opts.subcmd {
+        let diff = match maker.diff() {
+            Ok(v) => v,
+            Err(err) => fail(err),
+        };
+        if diff.is_empty() {
+            return println!("no drift");
+        }
+        diff.iter().for_each(|d| println!("{}", d));
+        process::exit(EXIT_DRIFT);
+    }
+    if let Some(SubCommand::Verify) = opts.subcmd {
+        let drift = match maker.verify() {
+            Ok(v) => v,
+            Err(err) => fail(err),
+        };
+        if drift.is_empty() {
+            return println!("no drift");
+        }
+        drift.iter().for_each(|d| println!("{}", d));
+        process::exit(EXIT_DRIFT);
+    }
+    let cleanup = if opts.backup {
+        Cleanup::Backup
+    } else if opts.force {
+        Cleanup::Remove
+    } else {
+        Cleanup::Refuse
+    };
+    if let Some(SubCommand::Apply) = opts.subcmd {
+        let changes = match maker.changes(cleanup) {
+            Ok(v) => v,
+            Err(err) => fail(err),
+        };
+        changes.iter().for_each(|c| println!("{}", c));
+        if opts.dry_run {
+            return;
+        }
+        return match maker.apply(&changes, cleanup) {
+            Ok(_) => println!("{} changes applied", changes.len()),
+            Err(err) => fail(err),
+        };
+    }
+    if opts.dry_run {
+        return match maker.plan(cleanup) {
+            Ok(plan) if opts.format == "json" => println!("{}", plan.to_json()),
+            Ok(plan) => print!("{}", plan.to_tree()),
+            Err(err) => fail(err),
+        };
+    }
+    if let Err(err) = maker.make(cleanup) {
+        fail(err)
+    }
+}
@@ -1,562 +1,1089 @@
-use std::borrow::Cow;
-use std::env::current_exe;
-use std::fs::{self, create_dir, remove_dir, remove_file, DirEntry};
-use std::path::PathBuf;
-
-use crate::config::Config;
-use snafu::{ResultExt, Snafu};
-
-#[derive(Debug, Snafu)]
-pub enum MakerError {
-    #[snafu(display("cannot read current directory: {}", source))]
-    Read { source: std::io::Error },
-    #[snafu(display("cannot remove file {:?}: {}", path, source))]
-    RemoveFile {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-    #[snafu(display("cannot remove directory {:?}: {}", path, source))]
-    RemoveDirectory {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-    #[snafu(display(
-        "you have to clean directory first or use flag --force to delete automatically"
-    ))]
-    NotEmpty,
-    #[snafu(display("cannot make directory {:?}: {}", path, source))]
-    CreateDirectory {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-    #[snafu(display("cannot create file {:?}: {}", path, source))]
-    CreateFile {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-    CreateSymlink {
-        source: std::io::Error,
-        original: PathBuf,
-        link: PathBuf,
-    },
-}
-
-type MakerResult<T, E = MakerError> = std::result::Result<T, E>;
-
-fn get_current_file_name() -> String {
-    let path = PathBuf::from(current_exe().unwrap());
-    let file_name = path.file_name().unwrap().to_str().unwrap();
-    format!("./{}", file_name)
-}
-
-lazy_static! {
-    static ref ALLOWED_DIRECTORIES: Vec<PathBuf> = vec![PathBuf::from("./share")];
-    static ref ALLOWED_FILES: Vec<PathBuf> = vec![
-        PathBuf::from(format!("./{}", crate::CONFIG_FILE)),
-        PathBuf::from(get_current_file_name())
-    ];
-    static ref GAME_SHARE_SYMLINKS: Vec<&'static str> = vec!["data", "package", "CMD", "locale"];
-    static ref AUTH_SHARE_SYMLINKS: Vec<&'static str> = vec!["data", "locale"];
-    static ref DB_SHARE_SYMLINKS: Vec<&'static str> = vec![
-        "data",
-        "package",
-        "locale",
-        "item_proto.txt",
-        "item_names.txt",
-        "mob_proto.txt",
-        "mob_names.txt"
-    ];
-}
-
-#[derive(Debug)]
-pub struct Maker {
-    config: Config,
-    dirs: Vec<DirEntry>,
-    files: Vec<DirEntry>,
-}
-
-impl Maker {
-    pub fn new(config: Config) -> MakerResult<Self> {
-        let dirs = Self::get_entries()?
-            .into_iter()
-            .filter(|d| d.path().is_dir())
-            .collect::<Vec<_>>();
-        let files = Self::get_entries()?
-            .into_iter()
-            .filter(|d| d.path().is_file())
-            .collect::<Vec<_>>();
-
-        Ok(Self {
-            config,
-            dirs,
-            files,
-        })
-    }
-
-    fn get_entries() -> MakerResult<Vec<DirEntry>> {
-        Ok(std::fs::read_dir(".")
-            .context(Read)?
-            .filter_map(|d| d.ok())
-            .collect::<Vec<_>>())
-    }
-
-    pub fn check_current_directory(&self, force: bool) -> MakerResult<()> {
-        let mut not_allowed: Vec<&DirEntry> = vec![];
-
-        self.dirs.iter().for_each(|d| {
-            if !ALLOWED_DIRECTORIES.contains(&d.path()) {
-                not_allowed.push(d);
-            }
-        });
-
-        self.files.iter().for_each(|d| {
-            if !ALLOWED_FILES.contains(&d.path()) {
-                not_allowed.push(d);
-            }
-        });
-
-        if !not_allowed.is_empty() {
-            if !force {
-                return Err(MakerError::NotEmpty);
-            }
-
-            for e in not_allowed.iter() {
-                if e.path().is_dir() {
-                    remove_dir(e.path()).context(RemoveDirectory { path: e.path() })?
-                } else {
-                    remove_file(e.path()).context(RemoveFile { path: e.path() })?
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    fn make_db(&self) -> MakerResult<()> {
-        create_dir("db").context(CreateDirectory { path: "db" })?;
-
-        //symlinks
-        for s in DB_SHARE_SYMLINKS.iter() {
-            std::os::unix::fs::symlink(format!("../share/{}", s), format!("./db/{}", s)).context(
-                CreateSymlink {
-                    original: format!("../share/{}", s),
-                    link: format!("./db/{}", s),
-                },
-            )?;
-        }
-
-        // symlink db
-        std::os::unix::fs::symlink(
-            "../share/db",
-            format!("./db/db_{}", self.config.server_name.to_lowercase()),
-        )
-        .context(CreateSymlink {
-            original: "../share/db",
-            link: format!("./db/db_{}", self.config.server_name.to_lowercase()),
-        })?;
-
-        //
-
-        fs::write(
-            format!("./db/conf.txt"),
-            format!(
-                "BIND_PORT = {}
-SQL_ACCOUNT = \"{} {} {} {} {} {}\"
-SQL_COMMON = \"{} {} {} {} {} {}\"
-SQL_HOTBACKUP = \"{} {} {} {} {} {}\"
-SQL_PLAYER = \"{} {} {} {} {} {}\"
-TABLE_POSTFIX = \"{}\"
-DB_SLEEP_MSEC = {}
-CLIENT_HEART_FPS = {}
-HASH_PLAYER_LIFE_SEC = {}
-PLAYER_DELETE_LEVEL_LIMIT = {}
-PLAYER_ID_START = {}
-BACKUP_LIMIT_SEC = 3600
-WELCOME_MSG = \"DB Server has been started\"
-ITEM_ID_RANGE = {} {}
-TEST_SERVER = {}
-",
-                self.config.db.bind_port,
-                // account_sql
-                self.config.databases.account.ip,
-                self.config.databases.account.user,
-                self.config.databases.account.password,
-                self.config.databases.account.database,
-                self.config.databases.account.port,
-                self.config.databases.account.sock,
-                // common_sql
-                self.config.databases.common.ip,
-                self.config.databases.common.user,
-                self.config.databases.common.password,
-                self.config.databases.common.database,
-                self.config.databases.common.port,
-                self.config.databases.common.sock,
-                // hotbackup_sql
-                self.config.databases.hotbackup.ip,
-                self.config.databases.hotbackup.user,
-                self.config.databases.hotbackup.password,
-                self.config.databases.hotbackup.database,
-                self.config.databases.hotbackup.port,
-                self.config.databases.hotbackup.sock,
-                // player_sql
-                self.config.databases.player.ip,
-                self.config.databases.player.user,
-                self.config.databases.player.password,
-                self.config.databases.player.database,
-                self.config.databases.player.port,
-                self.config.databases.player.sock,
-                //
-                self.config.common.table_postfix,
-                self.config.db.db_sleep_msec,
-                self.config.db.client_heart_fps,
-                self.config.db.hash_player_life_sec,
-                self.config.db.player_delete_level_limit,
-                self.config.db.player_id_start,
-                self.config.db.item_id_range.start,
-                self.config.db.item_id_range.end,
-                self.config.db.test_server,
-            ),
-        )
-        .context(CreateFile {
-            path: format!("./db/conf.txt"),
-        })?;
-
-        Ok(())
-    }
-
-    fn make_auth(&self) -> MakerResult<()> {
-        // auth
-        create_dir("auth").context(CreateDirectory { path: "auth" })?;
-
-        // auth channels
-        for x in 1..=self.config.auth.ports.len() {
-            create_dir(format!("./auth/{}", x)).context(CreateDirectory {
-                path: format!("./auth/{}", x),
-            })?;
-
-            create_dir(format!("./auth/{}/log", x)).context(CreateDirectory {
-                path: format!("./auth/{}/log", x),
-            })?;
-
-            //symlinks
-            for s in AUTH_SHARE_SYMLINKS.iter() {
-                std::os::unix::fs::symlink(
-                    format!("../../share/{}", s),
-                    format!("./auth/{}/{}", x, s),
-                )
-                .context(CreateSymlink {
-                    original: format!("../../share/{}", s),
-                    link: format!("./auth/{}/{}", x, s),
-                })?;
-            }
-
-            // symlink auth
-            std::os::unix::fs::symlink(
-                format!(
-                    "../../share/game_{}",
-                    self.config.server_name.to_lowercase()
-                ),
-                format!("./auth/{}/auth{}", x, x),
-            )
-            .context(CreateSymlink {
-                original: format!(
-                    "../../share/game_{}",
-                    self.config.server_name.to_lowercase()
-                ),
-                link: format!("./auth/{}/auth{}", x, x),
-            })?;
-
-            fs::write(
-                format!("./auth/{}/CONFIG", x),
-                format!(
-                    "CHANNEL: {}
-HOSTNAME: auth{}
-PORT: {}
-P2P_PORT: {}
-DB_ADDR: {}
-DB_PORT: {}
-PLAYER_SQL: {} {} {} {} {} {}
-COMMON_SQL: {} {} {} {} {} {}
-LOG_SQL: {} {} {} {} {} {}
-TABLE_POSTFIX: {}
-PASSES_PER_SEC: {}
-PING_EVENT_SECOND_CYCLE: {}
-ADMINPAGE_PASSWORD: {}
-adminpage_ip: {}
-adminpage_ip1: {}
-adminpage_ip2: {}
-adminpage_ip3: {}
-AUTH_SERVER: {}
-TRAFFIC_PROFILE: {}
-",
-                    x,
-                    x,
-                    self.config.auth.ports[(x - 1)].port,
-                    self.config.auth.ports[(x - 1)].p2p_port,
-                    self.config.common.db_ip,
-                    self.config.common.db_port,
-                    // player_sql
-                    self.config.databases.account.ip,
-                    self.config.databases.account.user,
-                    self.config.databases.account.password,
-                    self.config.databases.account.database,
-                    self.config.databases.account.port,
-                    self.config.databases.account.sock,
-                    // common_sql
-                    self.config.databases.common.ip,
-                    self.config.databases.common.user,
-                    self.config.databases.common.password,
-                    self.config.databases.common.database,
-                    self.config.databases.common.port,
-                    self.config.databases.common.sock,
-                    // log_sql
-                    self.config.databases.log.ip,
-                    self.config.databases.log.user,
-                    self.config.databases.log.password,
-                    self.config.databases.log.database,
-                    self.config.databases.log.port,
-                    self.config.databases.log.sock,
-                    //
-                    self.config.common.table_postfix,
-                    self.config.common.passes_per_sec,
-                    self.config.common.ping_event_second_cycle,
-                    // adminpage
-                    self.config.adminpage_ips.password,
-                    self.config.adminpage_ips.adminpage_ip,
-                    self.config.adminpage_ips.adminpage_ip1,
-                    self.config.adminpage_ips.adminpage_ip2,
-                    self.config.adminpage_ips.adminpage_ip3,
-                    //
-                    self.config.auth.auth_server,
-                    self.config.auth.traffic_profile
-                ),
-            )
-            .context(CreateFile {
-                path: format!("./auth/{}/CONFIG", x),
-            })?
-        }
-
-        Ok(())
-    }
-
-    fn make_channels(&self) -> MakerResult<()> {
-        // channels
-        for x in &self.config.channels.settings {
-            create_dir(format!("./{}", x.channel_dir_name())).context(CreateDirectory {
-                path: format!("./{}", x.channel_dir_name()),
-            })?;
-
-            let maps = x.get_map_ids(&self.config.channels);
-
-            for part_id in 1..=maps.len() {
-                create_dir(format!("./{}/part{}", x.channel_dir_name(), part_id)).context(
-                    CreateDirectory {
-                        path: format!("./{}/part{}", x.channel_dir_name(), part_id),
-                    },
-                )?;
-
-                create_dir(format!("./{}/part{}/log", x.channel_dir_name(), part_id)).context(
-                    CreateDirectory {
-                        path: format!("./{}/part{}/log", x.channel_dir_name(), part_id),
-                    },
-                )?;
-
-                create_dir(format!("./{}/part{}/mark", x.channel_dir_name(), part_id)).context(
-                    CreateDirectory {
-                        path: format!("./{}/part{}/mark", x.channel_dir_name(), part_id),
-                    },
-                )?;
-
-                //symlinks
-                for s in GAME_SHARE_SYMLINKS.iter() {
-                    std::os::unix::fs::symlink(
-                        format!("../../share/{}", s),
-                        format!("./{}/part{}/{}", x.channel_dir_name(), part_id, s),
-                    )
-                    .context(CreateSymlink {
-                        original: format!("../../share/{}", s),
-                        link: format!("./{}/part{}/{}", x.channel_dir_name(), part_id, s),
-                    })?;
-                }
-
-                // symlink game
-                std::os::unix::fs::symlink(
-                    format!(
-                        "../../share/game_{}",
-                        self.config.server_name.to_lowercase()
-                    ),
-                    match x.rename.as_ref() {
-                        None => format!(
-                            "./{}/part{}/game{}_{}",
-                            x.channel_dir_name(),
-                            part_id,
-                            x.channel_id,
-                            part_id
-                        ),
-                        Some(val) => format!("./{}/part{}/{}", x.channel_dir_name(), part_id, val),
-                    },
-                )
-                .context(CreateSymlink {
-                    original: format!(
-                        "../../share/game_{}",
-                        self.config.server_name.to_lowercase()
-                    ),
-                    link: match x.rename.as_ref() {
-                        None => format!(
-                            "./{}/part{}/game{}_{}",
-                            x.channel_dir_name(),
-                            part_id,
-                            x.channel_id,
-                            part_id
-                        ),
-                        Some(val) => format!("./{}/part{}/{}", x.channel_dir_name(), part_id, val),
-                    },
-                })?;
-
-                fs::write(
-                    format!("./{}/part{}/CONFIG", x.channel_dir_name(), part_id),
-                    format!(
-                        "CHANNEL: {}
-HOSTNAME: part{}
-PORT: {}
-P2P_PORT: {}
-DB_ADDR: {}
-DB_PORT: {}
-PLAYER_SQL: {} {} {} {} {} {}
-COMMON_SQL: {} {} {} {} {} {}
-LOG_SQL: {} {} {} {} {} {}
-TABLE_POSTFIX: {}
-MAP_ALLOW: {}
-PASSES_PER_SEC: {}
-SAVE_EVENT_SECOND_CYCLE: {}
-PING_EVENT_SECOND_CYCLE: {}
-VIEW_RANGE: {}
-CHECK_MULTIHACK: {}
-LOCALE_SERVICE: {}
-ADMINPAGE_PASSWORD: {}
-adminpage_ip: {}
-adminpage_ip1: {}
-adminpage_ip2: {}
-adminpage_ip3: {}
-SPEEDHACK_LIMIT_COUNT: {}
-SPEEDHACK_LIMIT_BONUS: {}
-PK_PROTECT_LEVEL: {}
-MALL_URL: {}
-TRAFFIC_PROFILE: {}
-TEST_SERVER: {}
-MAX_LEVEL: {}
-g_bDisableItemBonusChangeTime: {}
-",
-                        x.channel_id,
-                        part_id,
-                        x.port,
-                        x.p2p_port,
-                        self.config.common.db_ip,
-                        self.config.common.db_port,
-                        // player_sql
-                        self.config.databases.player.ip,
-                        self.config.databases.player.user,
-                        self.config.databases.player.password,
-                        self.config.databases.player.database,
-                        self.config.databases.player.port,
-                        self.config.databases.player.sock,
-                        // common_sql
-                        self.config.databases.common.ip,
-                        self.config.databases.common.user,
-                        self.config.databases.common.password,
-                        self.config.databases.common.database,
-                        self.config.databases.common.port,
-                        self.config.databases.common.sock,
-                        // log_sql
-                        self.config.databases.log.ip,
-                        self.config.databases.log.user,
-                        self.config.databases.log.password,
-                        self.config.databases.log.database,
-                        self.config.databases.log.port,
-                        self.config.databases.log.sock,
-                        //
-                        self.config.common.table_postfix,
-                        maps[part_id - 1]
-                            .iter()
-                            .map(|m| format!(" {}", m))
-                            .collect::<String>(),
-                        self.config.common.passes_per_sec,
-                        self.config.common.save_event_second_cycle,
-                        self.config.common.ping_event_second_cycle,
-                        self.config.common.view_range,
-                        0,
-                        self.config.common.locale_service,
-                        // adminpage
-                        self.config.adminpage_ips.password,
-                        self.config.adminpage_ips.adminpage_ip,
-                        self.config.adminpage_ips.adminpage_ip1,
-                        self.config.adminpage_ips.adminpage_ip2,
-                        self.config.adminpage_ips.adminpage_ip3,
-                        //
-                        self.config.common.speedhack_limit_count,
-                        self.config.common.speedhack_limit_bonus,
-                        self.config.common.pk_protect_level,
-                        self.config.common.mall_url,
-                        self.config.common.traffic_profile,
-                        self.config.common.test_server,
-                        self.config.common.max_level,
-                        self.config.common.disable_item_bonus_change_time,
-                    ),
-                )
-                .context(CreateFile {
-                    path: format!("./{}/part{}/CONFIG", x.channel_dir_name(), part_id),
-                })?
-            }
-        }
-
-        Ok(())
-    }
-
-    fn make_start_script(&self) -> MakerResult<()> {
-        let mut start_script = format!(
-            "#!/bin/sh
-cd /home/{}/db && ./db_{}
-sleep 3\n",
-            self.config.server_name, self.config.server_name
-        );
-
-        for x in &self.config.channels.settings {
-            let maps = x.get_map_ids(&self.config.channels);
-
-            for part_id in 1..=maps.len() {
-                start_script.push_str(&format!(
-                    "cd /home/{}/{}/part{} && ./{}\n",
-                    self.config.server_name,
-                    x.channel_dir_name(),
-                    part_id,
-                    match x.rename {
-                        Some(ref v) => Cow::Borrowed(v),
-                        None => Cow::Owned(format!("game{}_{}", x.channel_id, part_id)),
-                    }
-                ))
-            }
-        }
-
-        for x in 1..=self.config.auth.ports.len() {
-            start_script.push_str(&format!(
-                "cd /home/{}/auth/{}/ && ./auth{}\n",
-                self.config.server_name, x, x
-            ))
-        }
-
-        start_script.push_str("cd ../..\n");
-
-        fs::write("./start.sh", start_script).context(CreateFile { path: "./start.sh" })?;
-
-        Ok(())
-    }
-
-    pub fn make(&self) -> MakerResult<()> {
-        self.make_auth()?;
-        self.make_channels()?;
-        self.make_db()?;
-        self.make_start_script()?;
-
-        Ok(())
-    }
-}
+use std::collections::{BTreeMap, BTreeSet};
+use std::env::current_exe;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config::{merge_extra, Config, DeployMode, Extra, ShareKind};
+use crate::filesystem::{FileKind, FileSystem, RealFileSystem};
+use crate::manifest::{self, Drift, ManifestError, MANIFEST_FILE};
+use crate::plan::{Entry, Plan};
+use crate::ports::PORT_LOCK_FILE;
+use crate::process::{Process, Role};
+use crate::reconcile::{self, Change, ReconcileError};
+use crate::template::{TemplateError, Templates, Variables, AUTH_CONFIG, DB_CONF, GAME_CONFIG};
+use crate::validation::{validate, Report};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum MakerError {
+    #[snafu(display("cannot read directory {:?}: {}", path, source))]
+    Read {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("cannot remove {:?}: {}", path, source))]
+    Remove {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display(
+        "you have to clean directory first or use flag --force to delete automatically"
+    ))]
+    NotEmpty,
+    #[snafu(display("cannot move {:?} to {:?}: {}", path, to, source))]
+    Move {
+        source: std::io::Error,
+        path: PathBuf,
+        to: PathBuf,
+    },
+    #[snafu(display("cannot make directory {:?}: {}", path, source))]
+    CreateDirectory {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("cannot create file {:?}: {}", path, source))]
+    CreateFile {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    CreateSymlink {
+        source: std::io::Error,
+        original: PathBuf,
+        link: PathBuf,
+    },
+    #[snafu(display("cannot deploy {:?} to {:?}: {}", from, path, source))]
+    Deploy {
+        source: std::io::Error,
+        from: PathBuf,
+        path: PathBuf,
+    },
+    #[snafu(display(
+        "an earlier run was interrupted, keep either {:?} or {:?} and run again",
+        path,
+        from
+    ))]
+    Interrupted { path: PathBuf, from: PathBuf },
+    #[snafu(display("share is incomplete:\n{}", problems.join("\n")))]
+    Share { problems: Vec<String> },
+    #[snafu(display("invalid config:\n{}", report))]
+    Invalid { report: Report },
+    #[snafu(display("{}", source))]
+    Manifest { source: ManifestError },
+    #[snafu(display("{}", source))]
+    Reconcile { source: ReconcileError },
+    #[snafu(display("{}", source))]
+    Template { source: TemplateError },
+}
+
+type MakerResult<T, E = MakerError> = std::result::Result<T, E>;
+
+fn get_current_file_name() -> OsString {
+    current_exe().unwrap().file_name().unwrap().to_os_string()
+}
+
+const BACKUP_DIRECTORY: &str = "backups";
+/// Templates in here override the built-in ones unless `--templates` points elsewhere
+const TEMPLATE_DIRECTORY: &str = "templates";
+/// `make` builds the tree in here before moving it into place
+const STAGING_DIRECTORY: &str = ".channels-maker.staging";
+/// Entries replaced by `make` wait in here until the new tree is in place
+const PREVIOUS_DIRECTORY: &str = ".channels-maker.previous";
+
+lazy_static! {
+    static ref ALLOWED_DIRECTORIES: Vec<OsString> = vec![
+        OsString::from("share"),
+        OsString::from(BACKUP_DIRECTORY),
+        OsString::from(TEMPLATE_DIRECTORY),
+        OsString::from(STAGING_DIRECTORY),
+        OsString::from(PREVIOUS_DIRECTORY)
+    ];
+    static ref ALLOWED_FILES: Vec<OsString> = vec![
+        OsString::from(crate::CONFIG_FILE),
+        OsString::from(PORT_LOCK_FILE),
+        OsString::from(MANIFEST_FILE),
+        get_current_file_name()
+    ];
+}
+
+/// What to do with entries of the server directory that are not whitelisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cleanup {
+    /// Refuse to run while there are any
+    Refuse,
+    Remove,
+    /// Move them, and the generated entries `make` replaces, into `backups/<timestamp>` so the
+    /// previous tree can be restored
+    Backup,
+}
+
+#[derive(Debug)]
+pub struct Maker {
+    config: Config,
+    /// Server directory every generated path is relative to
+    root: PathBuf,
+    /// Config file name when the config is read from inside `root`
+    config_file: Option<OsString>,
+    templates: Templates,
+    /// Names in `root` with their kind
+    entries: Vec<(OsString, Option<FileKind>)>,
+    /// What the previous run generated, `None` for trees made before there was a manifest
+    manifest: Option<manifest::Manifest>,
+    fs: Box<dyn FileSystem>,
+}
+
+impl Maker {
+    pub fn new(
+        config: Config,
+        root: PathBuf,
+        config_path: &Path,
+        template_dir: Option<&Path>,
+    ) -> MakerResult<Self> {
+        Self::with_file_system(
+            config,
+            root,
+            config_path,
+            template_dir,
+            Box::new(RealFileSystem),
+        )
+    }
+
+    /// Like `new`, but `root` is read and changed through `fs`. Templates are still read
+    /// from disk.
+    pub fn with_file_system(
+        config: Config,
+        root: PathBuf,
+        config_path: &Path,
+        template_dir: Option<&Path>,
+        fs: Box<dyn FileSystem>,
+    ) -> MakerResult<Self> {
+        let entries = fs.read_dir(&root).context(Read { path: &root })?;
+        let manifest_path = root.join(MANIFEST_FILE);
+        let manifest = match fs.read(&manifest_path) {
+            Ok(v) => Some(manifest::Manifest::parse(&v, &manifest_path).context(Manifest)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(err).context(Read {
+                    path: manifest_path,
+                })
+            }
+        };
+        let templates = match template_dir {
+            Some(v) => Templates::load(Some(v)),
+            None => Templates::load(Some(&root.join(TEMPLATE_DIRECTORY))),
+        }
+        .context(Template)?;
+
+        let config_file = match (config_path.parent(), config_path.file_name()) {
+            (Some(parent), Some(name)) if Self::same_dir(parent, &root) => {
+                Some(name.to_os_string())
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            config,
+            root,
+            config_file,
+            templates,
+            entries,
+            manifest,
+            fs,
+        })
+    }
+
+    fn same_dir(a: &Path, b: &Path) -> bool {
+        let a = if a.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            a
+        };
+
+        match (a.canonicalize(), b.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.root.join(path)
+    }
+
+    fn is_allowed(&self, name: &OsString, kind: Option<FileKind>) -> bool {
+        match kind {
+            Some(FileKind::Directory) => {
+                ALLOWED_DIRECTORIES.contains(name) || self.is_share_directory(name)
+            }
+            Some(_) => ALLOWED_FILES.contains(name) || Some(name) == self.config_file.as_ref(),
+            None => false,
+        }
+    }
+
+    /// Whether `name` in `root` holds the share directory.
+    fn is_share_directory(&self, name: &OsString) -> bool {
+        let share = &self.config.share.path;
+        share.is_relative() && share.components().next().map(|c| c.as_os_str()) == Some(name)
+    }
+
+    /// Names of the entries in `root` that are neither whitelisted, the config file nor listed
+    /// in the manifest.
+    fn get_not_allowed(&self) -> Vec<PathBuf> {
+        let owned = self.owned_top_level();
+        self.entries
+            .iter()
+            .filter(|(name, kind)| !self.is_allowed(name, *kind))
+            .map(|(name, _)| PathBuf::from(name))
+            .filter(|p| !owned.contains(p.as_path()))
+            .collect()
+    }
+
+    fn owned_top_level(&self) -> BTreeSet<&Path> {
+        match self.manifest {
+            Some(ref m) => m.top_level(),
+            None => BTreeSet::new(),
+        }
+    }
+
+    /// Entries `--force` and `--backup` deal with. Without a manifest every entry that is not
+    /// whitelisted, otherwise only those the new tree needs the name of.
+    fn in_the_way(&self, layout: &Plan) -> Vec<PathBuf> {
+        let mut not_allowed = self.get_not_allowed();
+        if self.manifest.is_some() {
+            not_allowed.retain(|p| layout.create.iter().any(|e| e.path() == p));
+        }
+
+        not_allowed
+    }
+
+    fn get_backup_directory() -> PathBuf {
+        Path::new(BACKUP_DIRECTORY)
+            .join(chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string())
+    }
+
+    /// Everything `make` would do, without touching the disk. `remove` lists the entries in
+    /// the way and the top level paths of the manifest the config does not describe anymore.
+    pub fn plan(&self, cleanup: Cleanup) -> MakerResult<Plan> {
+        let mut plan = self.layout()?;
+
+        let not_allowed = self.in_the_way(&plan);
+        if !not_allowed.is_empty() && cleanup == Cleanup::Refuse {
+            return Err(MakerError::NotEmpty);
+        }
+        if cleanup == Cleanup::Backup && !self.displaced(&plan, &not_allowed).is_empty() {
+            plan.backup = Some(Self::get_backup_directory());
+        }
+
+        let top_level = Self::top_level(&plan);
+        let dropped = self
+            .owned_top_level()
+            .into_iter()
+            .filter(|p| !top_level.contains(p) && self.exists(p))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        plan.remove = not_allowed;
+        plan.remove.extend(dropped);
+
+        Ok(plan)
+    }
+
+    /// Everything the config describes, relative to `root`, without any cleanup. The last
+    /// entry is the manifest of all others.
+    pub fn layout(&self) -> MakerResult<Plan> {
+        let mut plan = Plan::default();
+
+        let processes = self.config.processes();
+
+        self.plan_auth(&mut plan, &processes)?;
+        self.plan_channels(&mut plan, &processes)?;
+        self.plan_db(&mut plan, &processes[0])?;
+        self.plan_scripts(&mut plan, &processes);
+
+        if self.config.port_ranges.is_some() {
+            plan.file(PORT_LOCK_FILE, self.config.port_lock_json());
+        }
+
+        let manifest = manifest::Manifest::new(&plan, manifest::config_hash(&self.config));
+        plan.file(MANIFEST_FILE, manifest.to_json());
+
+        Ok(plan)
+    }
+
+    /// Puts share entry `name` into `dir` under `link`, symlinked, hard linked or copied.
+    fn plan_share(&self, plan: &mut Plan, mode: DeployMode, dir: &Path, name: &str, link: &str) {
+        let share = &self.config.share;
+        let path = dir.join(link);
+        match mode {
+            DeployMode::Symlink => plan.symlink(share.link_target(dir, name), path),
+            DeployMode::Hardlink => plan.hardlink(share.path.join(name), path),
+            DeployMode::Copy => plan.copy(share.path.join(name), path),
+        }
+    }
+
+    fn plan_db(&self, plan: &mut Plan, p: &Process) -> MakerResult<()> {
+        plan.directory(&p.dir);
+
+        //symlinks
+        let share = &self.config.share;
+        for l in share.db.iter() {
+            self.plan_share(plan, share.deploy.db, &p.dir, &l.name, &l.name);
+        }
+
+        // symlink db
+        self.plan_share(plan, share.deploy.db, &p.dir, &share.db_binary, &p.binary);
+
+        //
+
+        let c = &self.config;
+        let mut v = Variables::new();
+        v.insert("bind_port", c.db.bind_port.to_string());
+        v.insert("sql_account", c.databases.account.sql());
+        v.insert("sql_common", c.databases.common.sql());
+        v.insert("sql_hotbackup", c.databases.hotbackup.sql());
+        v.insert("sql_player", c.databases.player.sql());
+        v.insert("table_postfix", c.common.table_postfix.clone());
+        v.insert("db_sleep_msec", c.db.db_sleep_msec.to_string());
+        v.insert("client_heart_fps", c.db.client_heart_fps.to_string());
+        v.insert(
+            "hash_player_life_sec",
+            c.db.hash_player_life_sec.to_string(),
+        );
+        v.insert(
+            "player_delete_level_limit",
+            c.db.player_delete_level_limit.to_string(),
+        );
+        v.insert("player_id_start", c.db.player_id_start.to_string());
+        v.insert("item_id_range_start", c.db.item_id_range.start.to_string());
+        v.insert("item_id_range_end", c.db.item_id_range.end.to_string());
+        v.insert("test_server", c.db.test_server.to_string());
+
+        plan.file(
+            p.dir.join("conf.txt"),
+            self.render(DB_CONF, &v, &[&c.db.extra], " = ")?,
+        );
+
+        Ok(())
+    }
+
+    fn plan_auth(&self, plan: &mut Plan, processes: &[Process]) -> MakerResult<()> {
+        // auth
+        plan.directory("auth");
+
+        // auth channels
+        for p in processes.iter() {
+            let x = match p.role {
+                Role::Auth { id } => id,
+                _ => continue,
+            };
+
+            plan.directory(&p.dir);
+            plan.preserved_directory(p.dir.join("log"));
+
+            //symlinks
+            let share = &self.config.share;
+            for l in share.auth.iter() {
+                self.plan_share(plan, share.deploy.auth, &p.dir, &l.name, &l.name);
+            }
+
+            // symlink auth
+            let binary = self.config.game_binary();
+            self.plan_share(plan, share.deploy.auth, &p.dir, &binary, &p.binary);
+
+            let c = &self.config;
+            let mut v = Variables::new();
+            v.insert("channel", x.to_string());
+            v.insert("hostname", format!("auth{}", x));
+            v.insert("port", p.port.to_string());
+            v.insert("p2p_port", p.p2p_port.unwrap_or_default().to_string());
+            v.insert("db_addr", c.common.db_ip.clone());
+            v.insert("db_port", c.common.db_port.to_string());
+            v.insert("player_sql", c.databases.account.sql());
+            v.insert("common_sql", c.databases.common.sql());
+            v.insert("log_sql", c.databases.log.sql());
+            v.insert("table_postfix", c.common.table_postfix.clone());
+            v.insert("passes_per_sec", c.common.passes_per_sec.to_string());
+            v.insert(
+                "ping_event_second_cycle",
+                c.common.ping_event_second_cycle.to_string(),
+            );
+            self.insert_adminpage(&mut v);
+            v.insert("auth_server", c.auth.auth_server.clone());
+            v.insert("traffic_profile", c.auth.traffic_profile.to_string());
+
+            plan.file(
+                p.dir.join("CONFIG"),
+                self.render(
+                    AUTH_CONFIG,
+                    &v,
+                    &[&c.common.extra, &c.auth.extra, &c.auth.ports[x - 1].extra],
+                    ": ",
+                )?,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn plan_channels(&self, plan: &mut Plan, processes: &[Process]) -> MakerResult<()> {
+        // channels
+        for (i, x) in self.config.channels.settings.iter().enumerate() {
+            plan.directory(x.channel_dir_name().into_owned());
+
+            let parts = x.get_parts(&self.config.channels);
+
+            for p in processes.iter() {
+                let part_id = match p.role {
+                    Role::Game { setting, part } if setting == i => part,
+                    _ => continue,
+                };
+
+                plan.directory(&p.dir);
+                plan.preserved_directory(p.dir.join("log"));
+                plan.preserved_directory(p.dir.join("mark"));
+
+                //symlinks
+                let share = &self.config.share;
+                for l in share.game.iter() {
+                    self.plan_share(plan, share.deploy.game, &p.dir, &l.name, &l.name);
+                }
+
+                // symlink game
+                let binary = self.config.game_binary();
+                self.plan_share(plan, share.deploy.game, &p.dir, &binary, &p.binary);
+
+                let c = &self.config;
+                let common = c.part_common(x, part_id);
+                let mut v = Variables::new();
+                v.insert("channel", x.channel_id.to_string());
+                v.insert(
+                    "hostname",
+                    parts[part_id - 1]
+                        .hostname
+                        .clone()
+                        .unwrap_or_else(|| format!("part{}", part_id)),
+                );
+                v.insert("port", p.port.to_string());
+                v.insert("p2p_port", p.p2p_port.unwrap_or_default().to_string());
+                v.insert("db_addr", common.db_ip.clone());
+                v.insert("db_port", common.db_port.to_string());
+                v.insert("player_sql", c.databases.player.sql());
+                v.insert("common_sql", c.databases.common.sql());
+                v.insert("log_sql", c.databases.log.sql());
+                v.insert("table_postfix", common.table_postfix.clone());
+                v.insert(
+                    "map_allow",
+                    parts[part_id - 1]
+                        .maps
+                        .iter()
+                        .map(|m| format!(" {}", m))
+                        .collect::<String>(),
+                );
+                v.insert("passes_per_sec", common.passes_per_sec.to_string());
+                v.insert(
+                    "save_event_second_cycle",
+                    common.save_event_second_cycle.to_string(),
+                );
+                v.insert(
+                    "ping_event_second_cycle",
+                    common.ping_event_second_cycle.to_string(),
+                );
+                v.insert("view_range", common.view_range.to_string());
+                v.insert("check_multihack", 0.to_string());
+                v.insert("locale_service", common.locale_service.clone());
+                self.insert_adminpage(&mut v);
+                v.insert(
+                    "speedhack_limit_count",
+                    common.speedhack_limit_count.to_string(),
+                );
+                v.insert(
+                    "speedhack_limit_bonus",
+                    common.speedhack_limit_bonus.to_string(),
+                );
+                v.insert("pk_protect_level", common.pk_protect_level.to_string());
+                v.insert("mall_url", common.mall_url.clone());
+                v.insert("traffic_profile", common.traffic_profile.to_string());
+                v.insert("test_server", common.test_server.to_string());
+                v.insert("max_level", common.max_level.to_string());
+                v.insert(
+                    "disable_item_bonus_change_time",
+                    common.disable_item_bonus_change_time.to_string(),
+                );
+
+                let extra = [&c.common.extra, &x.extra, &parts[part_id - 1].extra];
+
+                plan.file(
+                    p.dir.join("CONFIG"),
+                    self.render(GAME_CONFIG, &v, &extra, ": ")?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders a template and merges the `extra` keys into it.
+    fn render(
+        &self,
+        template: &str,
+        variables: &Variables,
+        extra: &[&Extra],
+        separator: &str,
+    ) -> MakerResult<String> {
+        let out = self
+            .templates
+            .render(template, variables)
+            .context(Template)?;
+
+        Ok(merge_extra(&out, extra, separator))
+    }
+
+    fn insert_adminpage(&self, v: &mut Variables) {
+        let a = &self.config.adminpage_ips;
+        v.insert("adminpage_password", a.password.clone());
+        v.insert("adminpage_ip", a.adminpage_ip.clone());
+        v.insert("adminpage_ip1", a.adminpage_ip1.clone());
+        v.insert("adminpage_ip2", a.adminpage_ip2.clone());
+        v.insert("adminpage_ip3", a.adminpage_ip3.clone());
+    }
+
+    fn plan_scripts(&self, plan: &mut Plan, processes: &[Process]) {
+        let home = self.config.home_dir();
+
+        // start
+        let mut start_script = String::from(
+            "#!/bin/sh
+# start <dir> <binary>: run the core in the background and remember its pid
+start() {
+\tcd \"$1\" || return
+\t./\"$2\" &
+\techo $! > \"$2.pid\"
+}
+",
+        );
+        for p in processes.iter() {
+            start_script.push_str(&format!(
+                "start {}/{} {}\n",
+                home,
+                p.dir.display(),
+                p.binary
+            ));
+            if p.role == Role::Db {
+                start_script.push_str("sleep 3\n");
+            }
+        }
+        plan.file("start.sh", start_script);
+
+        // stop, reverse order so db goes down after every core using it
+        let mut stop_script = String::from(
+            "#!/bin/sh
+# stop <dir> <binary>: terminate the core started by start.sh and wait until it is gone
+stop() {
+\t[ -f \"$1/$2.pid\" ] || return 0
+\tpid=$(cat \"$1/$2.pid\")
+\tif kill \"$pid\" 2>/dev/null; then
+\t\twhile kill -0 \"$pid\" 2>/dev/null; do
+\t\t\tsleep 1
+\t\tdone
+\tfi
+\trm -f \"$1/$2.pid\"
+}
+",
+        );
+        for p in processes.iter().rev() {
+            stop_script.push_str(&format!("stop {}/{} {}\n", home, p.dir.display(), p.binary));
+        }
+        plan.file("stop.sh", stop_script);
+
+        // restart
+        plan.file(
+            "restart.sh",
+            format!("#!/bin/sh\nsh {0}/stop.sh\nsh {0}/start.sh\n", home),
+        );
+
+        // status
+        let mut status_script = String::from(
+            "#!/bin/sh
+# status <dir> <binary>: report whether the core from start.sh is still alive
+status() {
+\tif [ -f \"$1/$2.pid\" ] && kill -0 \"$(cat \"$1/$2.pid\")\" 2>/dev/null; then
+\t\techo \"$1/$2: running\"
+\telse
+\t\techo \"$1/$2: dead\"
+\tfi
+}
+",
+        );
+        for p in processes.iter() {
+            status_script.push_str(&format!(
+                "status {}/{} {}\n",
+                home,
+                p.dir.display(),
+                p.binary
+            ));
+        }
+        plan.file("status.sh", status_script);
+    }
+
+    /// Creates `entry` below `base`, which is `root` or the staging directory.
+    fn create(&self, entry: &Entry, base: &Path) -> MakerResult<()> {
+        let path = base.join(entry.path());
+        match entry {
+            Entry::Directory { .. } => self.fs.create_dir(&path).context(CreateDirectory { path }),
+            Entry::Symlink { target, .. } => {
+                self.fs.symlink(target, &path).context(CreateSymlink {
+                    original: target,
+                    link: path,
+                })
+            }
+            Entry::File { contents, .. } => self
+                .fs
+                .write(&path, contents.as_bytes())
+                .context(CreateFile { path }),
+            Entry::Hardlink { source, .. } => {
+                let from = self.path(source);
+                self.fs
+                    .hardlink(&from, &path)
+                    .context(Deploy { from, path })
+            }
+            Entry::Copy { source, .. } => {
+                let from = self.path(source);
+                self.fs.copy(&from, &path).context(Deploy { from, path })
+            }
+        }
+    }
+
+    /// Removes a file, symlink or whole directory. Symlinks are never followed, a link
+    /// into `share/` is removed itself and its target is left alone.
+    fn remove(&self, path: &Path) -> MakerResult<()> {
+        let path = self.path(path);
+        self.fs.remove(&path).context(Remove { path })
+    }
+
+    /// Everything symlinks point at in the share directory, with the kind each one needs to be.
+    fn share_targets(&self) -> Vec<(String, ShareKind)> {
+        let share = &self.config.share;
+        let mut targets: Vec<(String, ShareKind)> = vec![];
+
+        for l in share.game.iter().chain(&share.auth).chain(&share.db) {
+            match targets.iter_mut().find(|(n, _)| *n == l.name) {
+                // a name listed for several roles is checked with its strictest kind
+                Some(t) if t.1 == ShareKind::Any => t.1 = l.kind,
+                Some(_) => {}
+                None => targets.push((l.name.clone(), l.kind)),
+            }
+        }
+        targets.push((self.config.game_binary(), ShareKind::Executable));
+        targets.push((share.db_binary.clone(), ShareKind::Executable));
+
+        targets
+    }
+
+    /// Fails with every share entry that is missing or of the wrong kind, so no symlink
+    /// ends up dangling.
+    fn check_share(&self) -> MakerResult<()> {
+        let share = self.config.share.dir(&self.root);
+        let mut problems = vec![];
+
+        for (name, kind) in self.share_targets() {
+            let path = share.join(&name);
+            let problem = match (self.fs.kind(&path), kind) {
+                (Err(_), _) => "missing",
+                (Ok(k), ShareKind::Directory) if k != FileKind::Directory => "not a directory",
+                (Ok(FileKind::Directory), ShareKind::File)
+                | (Ok(FileKind::Directory), ShareKind::Executable) => "not a file",
+                (Ok(FileKind::File), ShareKind::Executable) => "not executable",
+                _ => continue,
+            };
+            problems.push(format!(
+                "{}: {}",
+                self.config.share.path.join(&name).display(),
+                problem
+            ));
+        }
+
+        if !problems.is_empty() {
+            return Err(MakerError::Share { problems });
+        }
+
+        Ok(())
+    }
+
+    /// Checks run before anything on disk is touched: the config and the contents of `share/`.
+    pub fn preflight(&self) -> MakerResult<()> {
+        let report = validate(&self.config, self.fs.as_ref(), &self.root);
+        if report.has_errors() {
+            return Err(MakerError::Invalid { report });
+        }
+
+        self.check_share()
+    }
+
+    /// Generates the tree in a staging directory inside `root` and moves it into place only
+    /// once every entry was created. Paths the previous manifest lists are replaced and other
+    /// entries in the way removed after the swap succeeded, with `Cleanup::Backup` both are
+    /// moved into the backup directory instead. On any failure `root` is left as it was.
+    /// Leftovers of an interrupted run are put back first, see `recover`.
+    pub fn make(&self, cleanup: Cleanup) -> MakerResult<()> {
+        self.preflight()?;
+
+        let layout = self.layout()?;
+        self.recover(&layout)?;
+
+        let not_allowed = self.in_the_way(&layout);
+        if !not_allowed.is_empty() && cleanup == Cleanup::Refuse {
+            return Err(MakerError::NotEmpty);
+        }
+        let staging = self.path(STAGING_DIRECTORY);
+        let previous = self.path(PREVIOUS_DIRECTORY);
+
+        self.fs
+            .create_dir(&staging)
+            .context(CreateDirectory { path: &staging })?;
+        if let Err(err) = layout
+            .create
+            .iter()
+            .try_for_each(|e| self.create(e, &staging))
+        {
+            let _ = self.fs.remove(&staging);
+            return Err(err);
+        }
+
+        let backup = match cleanup {
+            Cleanup::Backup if !self.displaced(&layout, &not_allowed).is_empty() => {
+                Some(self.path(Self::get_backup_directory()))
+            }
+            _ => None,
+        };
+        let result = self.swap(&layout, &not_allowed, backup.as_deref());
+
+        let _ = self.fs.remove(&staging);
+        let _ = self.fs.remove(&previous);
+
+        result
+    }
+
+    /// Puts back what an interrupted `make` left in the staging and previous directories.
+    /// Entries moved aside go back into `root` unless the swap already finished, files carried
+    /// over into the staged tree go back into their directories. Fails without touching
+    /// anything when both an entry and its replacement exist, the admin has to pick one.
+    fn recover(&self, layout: &Plan) -> MakerResult<()> {
+        let staging = self.path(STAGING_DIRECTORY);
+        let previous = self.path(PREVIOUS_DIRECTORY);
+
+        let staged = match self.fs.read_dir(&staging) {
+            Ok(v) => Some(v),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err).context(Read { path: &staging }),
+        };
+        // the staged top level entries are moved in last, none left means the swap finished
+        let swapped = staged.as_ref().is_none_or(|v| v.is_empty());
+
+        if !swapped {
+            let names = match self.fs.read_dir(&previous) {
+                Ok(v) => v,
+                Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+                Err(err) => return Err(err).context(Read { path: &previous }),
+            };
+            for (name, _) in names.iter() {
+                if self.exists(Path::new(name)) {
+                    return Err(MakerError::Interrupted {
+                        path: self.path(name),
+                        from: previous.join(name),
+                    });
+                }
+            }
+            for (name, _) in names.iter() {
+                let (from, to) = (previous.join(name), self.path(name));
+                self.fs.rename(&from, &to).context(Move {
+                    path: &from,
+                    to: &to,
+                })?;
+            }
+
+            let planned = layout
+                .create
+                .iter()
+                .map(|e| (e.path(), e))
+                .collect::<BTreeMap<_, _>>();
+            self.restore_carried_over(&planned, Path::new(""))?;
+        }
+
+        for dir in [&staging, &previous].iter() {
+            if self.fs.kind(dir).is_ok() {
+                self.fs.remove(dir).context(Remove { path: *dir })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves everything below `dir` in the staging directory that `planned` does not list back
+    /// into `root`.
+    fn restore_carried_over(
+        &self,
+        planned: &BTreeMap<&Path, &Entry>,
+        dir: &Path,
+    ) -> MakerResult<()> {
+        let staging = self.path(STAGING_DIRECTORY);
+        let full = staging.join(dir);
+        let names = self.fs.read_dir(&full).context(Read { path: &full })?;
+
+        for (name, _) in names {
+            let path = dir.join(&name);
+            match planned.get(path.as_path()) {
+                // symlinks are never followed, they are generated and point into share/
+                Some(Entry::Directory { .. }) => self.restore_carried_over(planned, &path)?,
+                Some(_) => {}
+                None => {
+                    let (from, to) = (staging.join(&path), self.path(&path));
+                    if self.exists(&path) {
+                        return Err(MakerError::Interrupted { path: to, from });
+                    }
+                    self.fs.rename(&from, &to).context(Move {
+                        path: &from,
+                        to: &to,
+                    })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Top level paths of `layout`.
+    fn top_level(layout: &Plan) -> Vec<&Path> {
+        layout
+            .create
+            .iter()
+            .map(|e| e.path())
+            .filter(|p| p.components().count() == 1)
+            .collect()
+    }
+
+    /// Existing top level entries `make` moves out of `root`: `not_allowed`, then what the
+    /// new tree replaces and what the manifest lists.
+    fn displaced(&self, layout: &Plan, not_allowed: &[PathBuf]) -> Vec<PathBuf> {
+        let mut displaced = not_allowed.to_vec();
+        displaced.extend(
+            Self::top_level(layout)
+                .into_iter()
+                .chain(self.owned_top_level())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .filter(|p| !not_allowed.iter().any(|n| n == p))
+                .filter(|p| self.exists(p))
+                .map(Path::to_path_buf),
+        );
+
+        displaced
+    }
+
+    /// Moves what the manifest does not list from generated directories into the staged ones
+    /// that still exist, everything in the way out of `root` and into `backup` if given, then
+    /// the staged top level entries in. Undoes all moves when one fails.
+    fn swap(
+        &self,
+        layout: &Plan,
+        not_allowed: &[PathBuf],
+        backup: Option<&Path>,
+    ) -> MakerResult<()> {
+        let staging = self.path(STAGING_DIRECTORY);
+        let previous = self.path(PREVIOUS_DIRECTORY);
+
+        let top_level = Self::top_level(layout);
+
+        // (from, to) of every rename done so far, undone in reverse order on failure
+        let mut moved: Vec<(PathBuf, PathBuf)> = vec![];
+        let mut result = self.carry_over(layout, &mut moved);
+        if result.is_ok() {
+            result = self
+                .fs
+                .create_dir(&previous)
+                .context(CreateDirectory { path: &previous });
+        }
+        if let (Ok(_), Some(backup)) = (&result, backup) {
+            result = self
+                .fs
+                .create_dir_all(backup)
+                .context(CreateDirectory { path: backup });
+        }
+
+        for path in self.displaced(layout, not_allowed) {
+            if result.is_err() {
+                break;
+            }
+            let from = self.path(&path);
+            let to = match backup {
+                Some(backup) => backup.join(&path),
+                None => previous.join(&path),
+            };
+            result = self.fs.rename(&from, &to).context(Move {
+                path: &from,
+                to: &to,
+            });
+            if result.is_ok() {
+                moved.push((from, to));
+            }
+        }
+
+        for path in top_level.iter() {
+            if result.is_err() {
+                break;
+            }
+            let (from, to) = (staging.join(path), self.path(path));
+            result = self.fs.rename(&from, &to).context(Move {
+                path: &from,
+                to: &to,
+            });
+            if result.is_ok() {
+                moved.push((from, to));
+            }
+        }
+
+        if result.is_err() {
+            for (from, to) in moved.iter().rev() {
+                let _ = self.fs.rename(to, from);
+            }
+        }
+
+        result
+    }
+
+    /// Moves entries the manifest does not list, like logs or files an admin added, from the
+    /// generated directories into their staged counterparts. Directories the config does not
+    /// describe anymore are removed with everything in them.
+    fn carry_over(&self, layout: &Plan, moved: &mut Vec<(PathBuf, PathBuf)>) -> MakerResult<()> {
+        let manifest = match self.manifest {
+            Some(ref v) => v,
+            None => return Ok(()),
+        };
+        let listed = manifest.paths();
+        let staging = self.path(STAGING_DIRECTORY);
+        let staged = layout
+            .create
+            .iter()
+            .filter_map(|e| match e {
+                Entry::Directory { path, .. } => Some(path.as_path()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+
+        for dir in listed.iter().filter(|p| staged.contains(*p)) {
+            // never move anything out of a directory a symlink was put in place of
+            if dir
+                .ancestors()
+                .any(|a| self.fs.read_link(&self.path(a)).is_ok())
+            {
+                continue;
+            }
+            let names = match self.fs.read_dir(&self.path(dir)) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for (name, _) in names {
+                let path = dir.join(&name);
+                let (from, to) = (self.path(&path), staging.join(&path));
+                if listed.contains(path.as_path()) || self.fs.kind(&to).is_ok() {
+                    continue;
+                }
+                self.fs.rename(&from, &to).context(Move {
+                    path: &from,
+                    to: &to,
+                })?;
+                moved.push((from, to));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `path` exists in `root`, dangling symlinks included.
+    fn exists(&self, path: &Path) -> bool {
+        let path = self.path(path);
+        self.fs.kind(&path).is_ok() || self.fs.read_link(&path).is_ok()
+    }
+
+    /// What `apply` has to do to bring the current directory in line with the config. Entries
+    /// that are not whitelisted are only looked into when the layout needs their name, or
+    /// all of them with `Cleanup::Remove` and `Cleanup::Backup` like `make` does.
+    pub fn changes(&self, cleanup: Cleanup) -> MakerResult<Vec<Change>> {
+        let layout = self.layout()?;
+        let mut managed = self.in_the_way(&layout);
+        if cleanup == Cleanup::Refuse {
+            managed.retain(|p| layout.create.iter().any(|e| e.path() == p));
+        }
+        managed.extend(self.owned_top_level().into_iter().map(Path::to_path_buf));
+        let owned = self.manifest.as_ref().map(manifest::Manifest::paths);
+
+        reconcile::changes(
+            self.fs.as_ref(),
+            &self.root,
+            &layout,
+            &managed,
+            owned.as_ref(),
+        )
+        .context(Reconcile)
+    }
+
+    /// `changes` described for people, see `Change::describe`. The manifest is left out, its
+    /// hashes change with every edit of the config and say nothing about the tree.
+    pub fn diff(&self) -> MakerResult<Vec<String>> {
+        self.changes(Cleanup::Refuse)?
+            .iter()
+            .filter(|c| c.path() != Path::new(MANIFEST_FILE))
+            .map(|c| c.describe(self.fs.as_ref(), &self.root).context(Reconcile))
+            .collect()
+    }
+
+    /// Differences between the tree and the manifest of the last run.
+    pub fn verify(&self) -> MakerResult<Vec<Drift>> {
+        let manifest = match self.manifest {
+            Some(ref v) => v,
+            None => {
+                return Err(ManifestError::Missing {
+                    path: self.path(MANIFEST_FILE),
+                })
+                .context(Manifest)
+            }
+        };
+
+        manifest
+            .drift(
+                self.fs.as_ref(),
+                &self.root,
+                &manifest::config_hash(&self.config),
+            )
+            .context(Manifest)
+    }
+
+    /// Carries out `changes`, with `Cleanup::Backup` removed paths are moved into the backup
+    /// directory instead.
+    pub fn apply(&self, changes: &[Change], cleanup: Cleanup) -> MakerResult<()> {
+        self.preflight()?;
+
+        let backup = self.path(Self::get_backup_directory());
+        for c in changes.iter() {
+            match c {
+                Change::Create(e) => self.create(e, &self.root)?,
+                Change::Update(e) => {
+                    self.remove(e.path())?;
+                    self.create(e, &self.root)?
+                }
+                Change::Remove(path) if cleanup == Cleanup::Backup => {
+                    let (from, to) = (self.path(path), backup.join(path));
+                    if let Some(parent) = to.parent() {
+                        self.fs
+                            .create_dir_all(parent)
+                            .context(CreateDirectory { path: parent })?;
+                    }
+                    self.fs.rename(&from, &to).context(Move {
+                        path: &from,
+                        to: &to,
+                    })?
+                }
+                Change::Remove(path) => self.remove(path)?,
+            }
+        }
+
+        Ok(())
+    }
+}
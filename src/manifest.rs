@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::deploy;
+use crate::filesystem::{FileKind, FileSystem};
+use crate::plan::{Entry, Plan};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum ManifestError {
+    #[snafu(display("cannot parse {:?}: {}", path, source))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("{:?} does not exist, the tree was not generated yet", path))]
+    Missing { path: PathBuf },
+    #[snafu(display("cannot inspect {:?}: {}", path, source))]
+    Inspect {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+}
+
+type ManifestResult<T, E = ManifestError> = std::result::Result<T, E>;
+
+/// Every path the maker generated, written into the server directory. Only paths listed here
+/// are replaced or removed by later runs.
+pub const MANIFEST_FILE: &str = ".channels-maker.lock.json";
+
+/// SHA-256 of the config a tree is generated from, after ranges and balance are expanded.
+pub fn config_hash(config: &Config) -> String {
+    deploy::sha256(serde_json::to_string(config).unwrap().as_bytes())
+}
+
+/// A generated path as recorded in the manifest. Files keep the hash of their contents
+/// instead of the contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ManifestEntry {
+    Directory {
+        #[serde(rename = "path")]
+        path: PathBuf,
+    },
+    Symlink {
+        #[serde(rename = "path")]
+        path: PathBuf,
+        #[serde(rename = "target")]
+        target: PathBuf,
+    },
+    File {
+        #[serde(rename = "path")]
+        path: PathBuf,
+        #[serde(rename = "sha256")]
+        sha256: String,
+    },
+    Hardlink {
+        #[serde(rename = "path")]
+        path: PathBuf,
+        #[serde(rename = "source")]
+        source: PathBuf,
+    },
+    Copy {
+        #[serde(rename = "path")]
+        path: PathBuf,
+        #[serde(rename = "source")]
+        source: PathBuf,
+    },
+}
+
+impl ManifestEntry {
+    pub fn path(&self) -> &Path {
+        match self {
+            ManifestEntry::Directory { path } => path,
+            ManifestEntry::Symlink { path, .. } => path,
+            ManifestEntry::File { path, .. } => path,
+            ManifestEntry::Hardlink { path, .. } => path,
+            ManifestEntry::Copy { path, .. } => path,
+        }
+    }
+}
+
+impl From<&Entry> for ManifestEntry {
+    fn from(e: &Entry) -> Self {
+        match e {
+            Entry::Directory { path, .. } => ManifestEntry::Directory { path: path.clone() },
+            Entry::Symlink { path, target } => ManifestEntry::Symlink {
+                path: path.clone(),
+                target: target.clone(),
+            },
+            Entry::File { path, contents } => ManifestEntry::File {
+                path: path.clone(),
+                sha256: deploy::sha256(contents.as_bytes()),
+            },
+            Entry::Hardlink { path, source } => ManifestEntry::Hardlink {
+                path: path.clone(),
+                source: source.clone(),
+            },
+            Entry::Copy { path, source } => ManifestEntry::Copy {
+                path: path.clone(),
+                source: source.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(rename = "config_hash")]
+    pub config_hash: String,
+    #[serde(rename = "entries")]
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Difference between the tree on disk and the manifest it was generated with.
+#[derive(Debug)]
+pub enum Drift {
+    /// The config was changed after the tree was generated.
+    Config,
+    Missing(PathBuf),
+    /// Path exists, but is not what was generated.
+    Changed {
+        path: PathBuf,
+        problem: String,
+    },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Config => write!(f, "config changed since the tree was generated"),
+            Drift::Missing(p) => write!(f, "{}: missing", p.display()),
+            Drift::Changed { path, problem } => write!(f, "{}: {}", path.display(), problem),
+        }
+    }
+}
+
+impl Manifest {
+    /// Manifest of everything `plan` creates.
+    pub fn new(plan: &Plan, config_hash: String) -> Self {
+        Self {
+            config_hash,
+            entries: plan.create.iter().map(ManifestEntry::from).collect(),
+        }
+    }
+
+    pub fn parse(data: &[u8], path: &Path) -> ManifestResult<Self> {
+        serde_json::from_slice(data).context(Parse { path })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap() + "\n"
+    }
+
+    pub fn paths(&self) -> BTreeSet<&Path> {
+        self.entries.iter().map(|e| e.path()).collect()
+    }
+
+    /// Names directly in the server directory the manifest lists.
+    pub fn top_level(&self) -> BTreeSet<&Path> {
+        self.paths()
+            .into_iter()
+            .filter(|p| p.components().count() == 1)
+            .collect()
+    }
+
+    /// Compares every listed path with the tree in `root` as `fs` sees it, and `config_hash`
+    /// with the hash the tree was generated from.
+    pub fn drift(
+        &self,
+        fs: &dyn FileSystem,
+        root: &Path,
+        config_hash: &str,
+    ) -> ManifestResult<Vec<Drift>> {
+        let mut drift = vec![];
+        if self.config_hash != config_hash {
+            drift.push(Drift::Config);
+        }
+
+        for e in self.entries.iter() {
+            let path = root.join(e.path());
+            // symlinks are looked at themselves, dangling ones included
+            let link = fs.read_link(&path).ok();
+            let kind = match fs.kind(&path) {
+                Ok(_) if link.is_some() => None,
+                Ok(v) => Some(v),
+                Err(err) if err.kind() == ErrorKind::NotFound => None,
+                Err(err) => return Err(err).context(Inspect { path }),
+            };
+            if link.is_none() && kind.is_none() {
+                drift.push(Drift::Missing(e.path().to_path_buf()));
+                continue;
+            }
+
+            let problem = match (e, link) {
+                (ManifestEntry::Directory { .. }, _) if kind != Some(FileKind::Directory) => {
+                    "not a directory anymore".to_string()
+                }
+                (ManifestEntry::Symlink { .. }, None) => "not a symlink anymore".to_string(),
+                (ManifestEntry::Symlink { target, .. }, Some(actual)) => {
+                    if actual == *target {
+                        continue;
+                    }
+                    format!(
+                        "points to {} instead of {}",
+                        actual.display(),
+                        target.display()
+                    )
+                }
+                (ManifestEntry::File { .. }, _)
+                    if kind.is_none() || kind == Some(FileKind::Directory) =>
+                {
+                    "not a file anymore".to_string()
+                }
+                (ManifestEntry::File { sha256, .. }, _) => {
+                    let contents = fs.read(&path).context(Inspect { path: &path })?;
+                    if deploy::sha256(&contents) == *sha256 {
+                        continue;
+                    }
+                    "contents changed".to_string()
+                }
+                (ManifestEntry::Hardlink { source, .. }, _) => {
+                    if fs
+                        .is_hardlinked(&root.join(source), &path)
+                        .context(Inspect { path: &path })?
+                    {
+                        continue;
+                    }
+                    format!("not hard linked to {} anymore", source.display())
+                }
+                (ManifestEntry::Copy { source, .. }, _) => {
+                    let full = root.join(source);
+                    if fs.checksum(&full).context(Inspect { path: &full })?
+                        == fs.checksum(&path).context(Inspect { path: &path })?
+                    {
+                        continue;
+                    }
+                    format!("differs from {}", source.display())
+                }
+                _ => continue,
+            };
+            drift.push(Drift::Changed {
+                path: e.path().to_path_buf(),
+                problem,
+            });
+        }
+
+        Ok(drift)
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+
+/// Single filesystem object the maker wants to create, relative to the server directory.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    Directory {
+        path: PathBuf,
+        /// Contents are written by the cores (logs, guild marks) and must survive regeneration
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        preserve: bool,
+    },
+    Symlink {
+        path: PathBuf,
+        target: PathBuf,
+    },
+    File {
+        path: PathBuf,
+        contents: String,
+    },
+    /// Hard link to a share entry, directories are recreated with every file linked
+    Hardlink {
+        path: PathBuf,
+        source: PathBuf,
+    },
+    /// Copy of a share entry, replaced when its checksum differs from the source
+    Copy {
+        path: PathBuf,
+        source: PathBuf,
+    },
+}
+
+impl Entry {
+    pub fn path(&self) -> &Path {
+        match self {
+            Entry::Directory { path, .. } => path,
+            Entry::Symlink { path, .. } => path,
+            Entry::File { path, .. } => path,
+            Entry::Hardlink { path, .. } => path,
+            Entry::Copy { path, .. } => path,
+        }
+    }
+}
+
+/// Complete change set of a run: what `--force` deletes and what gets created, in order.
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub remove: Vec<PathBuf>,
+    /// Archive directory `remove` is moved to instead of being deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+    pub create: Vec<Entry>,
+}
+
+impl Plan {
+    pub fn directory<P: Into<PathBuf>>(&mut self, path: P) {
+        self.create.push(Entry::Directory {
+            path: path.into(),
+            preserve: false,
+        })
+    }
+
+    pub fn preserved_directory<P: Into<PathBuf>>(&mut self, path: P) {
+        self.create.push(Entry::Directory {
+            path: path.into(),
+            preserve: true,
+        })
+    }
+
+    pub fn symlink<T: Into<PathBuf>, P: Into<PathBuf>>(&mut self, target: T, path: P) {
+        self.create.push(Entry::Symlink {
+            path: path.into(),
+            target: target.into(),
+        })
+    }
+
+    pub fn hardlink<S: Into<PathBuf>, P: Into<PathBuf>>(&mut self, source: S, path: P) {
+        self.create.push(Entry::Hardlink {
+            path: path.into(),
+            source: source.into(),
+        })
+    }
+
+    pub fn copy<S: Into<PathBuf>, P: Into<PathBuf>>(&mut self, source: S, path: P) {
+        self.create.push(Entry::Copy {
+            path: path.into(),
+            source: source.into(),
+        })
+    }
+
+    pub fn file<P: Into<PathBuf>>(&mut self, path: P, contents: String) {
+        self.create.push(Entry::File {
+            path: path.into(),
+            contents,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_tree(&self) -> String {
+        let mut out = String::new();
+
+        if !self.remove.is_empty() {
+            match self.backup {
+                Some(ref v) => writeln!(out, "move to {}:", v.display()).unwrap(),
+                None => out.push_str("remove:\n"),
+            }
+            let mut root = Node::default();
+            for path in self.remove.iter() {
+                root.insert(path, Some(String::new()));
+            }
+            root.render(&mut out, 1);
+        }
+
+        out.push_str("create:\n");
+        let mut root = Node::default();
+        for e in self.create.iter() {
+            let label = match e {
+                Entry::Directory { .. } => None,
+                Entry::Symlink { target, .. } => Some(format!(" -> {}", target.display())),
+                Entry::File { contents, .. } => Some(format!(" ({} bytes)", contents.len())),
+                Entry::Hardlink { source, .. } => {
+                    Some(format!(" (hardlink of {})", source.display()))
+                }
+                Entry::Copy { source, .. } => Some(format!(" (copy of {})", source.display())),
+            };
+            root.insert(e.path(), label);
+        }
+        root.render(&mut out, 1);
+
+        out
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    label: Option<String>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &Path, label: Option<String>) {
+        let mut node = self;
+        for c in path.components() {
+            if let Component::Normal(name) = c {
+                node = node
+                    .children
+                    .entry(name.to_string_lossy().into_owned())
+                    .or_default();
+            }
+        }
+        node.label = label;
+    }
+
+    fn render(&self, out: &mut String, depth: usize) {
+        for (name, node) in self.children.iter() {
+            let suffix = match node.label {
+                Some(ref v) => v.as_str(),
+                None => "/",
+            };
+            writeln!(out, "{}{}{}", "  ".repeat(depth), name, suffix).unwrap();
+            node.render(out, depth + 1);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::config::{auth_port_key, part_port_key, AllocatedPorts, Config, PortRange};
+use crate::process::Role;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum PortsError {
+    #[snafu(display("cannot read {:?}: {}", path, source))]
+    ReadLock {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("cannot parse {:?}: {}", path, source))]
+    ParseLock {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("{} has no {} and port_ranges.{} is not set", key, field, range))]
+    NoRange {
+        key: String,
+        field: &'static str,
+        range: &'static str,
+    },
+    #[snafu(display("port_ranges.{} has no free port left for {}", range, key))]
+    Exhausted { key: String, range: &'static str },
+}
+
+type PortsResult<T, E = PortsError> = std::result::Result<T, E>;
+
+/// Ports of every channel part and auth, written into the server directory so allocations
+/// stay stable between runs and can be reviewed or pinned.
+pub const PORT_LOCK_FILE: &str = "ports.lock.json";
+
+pub type PortLock = BTreeMap<String, AllocatedPorts>;
+
+/// Reads `PORT_LOCK_FILE` from `root`, empty if it does not exist yet.
+pub fn read_lock(root: &Path) -> PortsResult<PortLock> {
+    let path = root.join(PORT_LOCK_FILE);
+    if !path.exists() {
+        return Ok(PortLock::new());
+    }
+
+    let data = read_to_string(&path).context(ReadLock { path: &path })?;
+    serde_json::from_str(&data).context(ParseLock { path })
+}
+
+/// Port without a value in the config.
+struct Request {
+    key: String,
+    field: &'static str,
+    range_name: &'static str,
+    range: Option<PortRange>,
+    locked: Option<i64>,
+    value: Option<i64>,
+}
+
+impl Config {
+    /// Fills `allocated_ports` for every part and auth port missing from the config. Ports
+    /// recorded in `lock` are kept while they are still in range and free, the rest get the
+    /// lowest free port of their range in process order.
+    pub fn allocate_ports(&mut self, lock: &PortLock) -> PortsResult<()> {
+        let ranges = self.port_ranges.as_ref();
+        let mut used: HashSet<i64> = HashSet::new();
+        let mut requests: Vec<Request> = vec![];
+
+        used.insert(self.db.bind_port);
+
+        let mut wanted = |key: String,
+                          explicit: [Option<i64>; 2],
+                          ranges: [(&'static str, Option<PortRange>); 2]| {
+            let locked = lock.get(&key);
+            for (i, (field, value)) in ["port", "p2p_port"].iter().zip(explicit.iter()).enumerate()
+            {
+                match value {
+                    Some(v) => {
+                        used.insert(*v);
+                    }
+                    None => requests.push(Request {
+                        key: key.clone(),
+                        field,
+                        range_name: ranges[i].0,
+                        range: ranges[i].1,
+                        locked: locked.map(|l| if i == 0 { l.port } else { l.p2p_port }),
+                        value: None,
+                    }),
+                }
+            }
+        };
+
+        // ports are still 0 here, only the roles are needed
+        for p in self.processes().iter() {
+            match p.role {
+                Role::Game { setting, part } => {
+                    let (port, p2p_port) = self.explicit_part_ports(setting, part);
+                    wanted(
+                        part_port_key(self.channels.settings[setting].channel_id, part),
+                        [port.map(|p| p.value), p2p_port.map(|p| p.value)],
+                        [
+                            ("game", ranges.and_then(|r| r.game)),
+                            ("p2p", ranges.and_then(|r| r.p2p)),
+                        ],
+                    );
+                }
+                Role::Auth { id } => {
+                    let (port, p2p_port) = self.explicit_auth_ports(id);
+                    wanted(
+                        auth_port_key(id),
+                        [port.map(|p| p.value), p2p_port.map(|p| p.value)],
+                        [
+                            ("auth", ranges.and_then(|r| r.auth)),
+                            ("p2p", ranges.and_then(|r| r.p2p)),
+                        ],
+                    );
+                }
+                Role::Db => {}
+            }
+        }
+
+        // previous allocations first, so new processes never take a port over
+        for r in requests.iter_mut() {
+            if let (Some(range), Some(locked)) = (r.range, r.locked) {
+                if range.contains(locked) && used.insert(locked) {
+                    r.value = Some(locked);
+                }
+            }
+        }
+
+        for r in requests.iter_mut().filter(|r| r.value.is_none()) {
+            let range = r.range.ok_or_else(|| PortsError::NoRange {
+                key: r.key.clone(),
+                field: r.field,
+                range: r.range_name,
+            })?;
+            let port = (range.start..=range.end)
+                .find(|p| !used.contains(p))
+                .ok_or_else(|| PortsError::Exhausted {
+                    key: r.key.clone(),
+                    range: r.range_name,
+                })?;
+            used.insert(port);
+            r.value = Some(port);
+        }
+
+        self.allocated_ports.clear();
+        for r in requests.iter() {
+            let entry = self
+                .allocated_ports
+                .entry(r.key.clone())
+                .or_insert(AllocatedPorts {
+                    port: 0,
+                    p2p_port: 0,
+                });
+            match r.field {
+                "port" => entry.port = r.value.unwrap(),
+                _ => entry.p2p_port = r.value.unwrap(),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Contents of `PORT_LOCK_FILE`: the resolved ports of every channel part and auth.
+    pub fn port_lock_json(&self) -> String {
+        let lock: PortLock = self
+            .processes()
+            .iter()
+            .filter_map(|p| {
+                let key = match p.role {
+                    Role::Game { setting, part } => {
+                        part_port_key(self.channels.settings[setting].channel_id, part)
+                    }
+                    Role::Auth { id } => auth_port_key(id),
+                    Role::Db => return None,
+                };
+                Some((
+                    key,
+                    AllocatedPorts {
+                        port: p.port,
+                        p2p_port: p.p2p_port.unwrap_or_default(),
+                    },
+                ))
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&lock).unwrap() + "\n"
+    }
+}
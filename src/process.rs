@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Db,
+    /// `setting` is the index into `channels.settings`, `part` starts at 1
+    Game {
+        setting: usize,
+        part: usize,
+    },
+    /// `id` starts at 1, `auth.ports[id - 1]` holds its ports
+    Auth {
+        id: usize,
+    },
+}
+
+/// Single core process of the cluster.
+#[derive(Debug)]
+pub struct Process {
+    pub role: Role,
+    /// Working directory relative to the server directory
+    pub dir: PathBuf,
+    /// Name of the binary symlink inside `dir`
+    pub binary: String,
+    /// Listening port, `bind_port` for db
+    pub port: i64,
+    /// P2P port of cores, `None` for db
+    pub p2p_port: Option<i64>,
+}
+
+impl Config {
+    /// Every process of the cluster in start order: db, each channel part, then auth.
+    /// The generated tree, scripts and units are all derived from this list.
+    pub fn processes(&self) -> Vec<Process> {
+        let mut processes = vec![Process {
+            role: Role::Db,
+            dir: PathBuf::from("db"),
+            binary: format!("db_{}", self.server_name.to_lowercase()),
+            port: self.db.bind_port,
+            p2p_port: None,
+        }];
+
+        for (i, x) in self.channels.settings.iter().enumerate() {
+            for (part, definition) in (1..).zip(x.get_parts(&self.channels).iter()) {
+                let (port, p2p_port) = self.part_ports(i, part);
+                processes.push(Process {
+                    role: Role::Game { setting: i, part },
+                    dir: PathBuf::from(format!("{}/part{}", x.channel_dir_name(), part)),
+                    binary: match (&definition.binary, &x.rename) {
+                        (Some(v), _) | (None, Some(v)) => v.clone(),
+                        (None, None) => format!("game{}_{}", x.channel_id, part),
+                    },
+                    port: port.value,
+                    p2p_port: Some(p2p_port.value),
+                });
+            }
+        }
+
+        for id in 1..=self.auth.ports.len() {
+            let (port, p2p_port) = self.auth_ports(id);
+            processes.push(Process {
+                role: Role::Auth { id },
+                dir: PathBuf::from(format!("auth/{}", id)),
+                binary: format!("auth{}", id),
+                port: port.value,
+                p2p_port: Some(p2p_port.value),
+            });
+        }
+
+        processes
+    }
+}
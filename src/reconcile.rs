@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::diff;
+use crate::filesystem::{FileKind, FileSystem};
+use crate::plan::{Entry, Plan};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum ReconcileError {
+    #[snafu(display("cannot inspect {:?}: {}", path, source))]
+    Inspect {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+}
+
+type ReconcileResult<T, E = ReconcileError> = std::result::Result<T, E>;
+
+/// Difference between the tree on disk and the planned one.
+#[derive(Debug)]
+pub enum Change {
+    /// Path does not exist yet.
+    Create(Entry),
+    /// Path exists with the wrong type, symlink target or contents, or is a copy of a share
+    /// entry that changed since, it is replaced.
+    Update(Entry),
+    /// Directory or symlink that the config does not describe anymore.
+    Remove(PathBuf),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Create(e) => write!(f, "+ {}", e.path().display()),
+            Change::Update(e) => write!(f, "~ {}", e.path().display()),
+            Change::Remove(p) => write!(f, "- {}", p.display()),
+        }
+    }
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Create(e) | Change::Update(e) => e.path(),
+            Change::Remove(p) => p,
+        }
+    }
+
+    /// What differs on disk, for people: a unified diff for files, one line otherwise.
+    pub fn describe(&self, fs: &dyn FileSystem, root: &Path) -> ReconcileResult<String> {
+        let (entry, exists) = match self {
+            Change::Create(e) => (e, false),
+            Change::Update(e) => (e, true),
+            Change::Remove(p) => return Ok(format!("extra {}", p.display())),
+        };
+        let path = entry.path();
+        let full = root.join(path);
+        let found = if exists { inspect(fs, &full)? } else { None };
+
+        Ok(match (entry, found) {
+            (Entry::Directory { .. }, None) => format!("missing directory {}", path.display()),
+            (Entry::Symlink { target, .. }, None) => {
+                format!("missing symlink {} -> {}", path.display(), target.display())
+            }
+            (Entry::File { .. }, None) => format!("missing file {}", path.display()),
+            (Entry::Hardlink { source, .. }, None) => format!(
+                "missing {} (hardlink of {})",
+                path.display(),
+                source.display()
+            ),
+            (Entry::Copy { source, .. }, None) => {
+                format!("missing {} (copy of {})", path.display(), source.display())
+            }
+            (Entry::Symlink { target, .. }, Some(Found::Symlink)) => format!(
+                "{} -> {}, expected {}",
+                path.display(),
+                fs.read_link(&full)
+                    .context(Inspect { path: &full })?
+                    .display(),
+                target.display()
+            ),
+            (Entry::File { contents, .. }, Some(Found::File)) => {
+                let current = fs.read(&full).context(Inspect { path: &full })?;
+                let name = path.display();
+                diff::unified(
+                    &format!("{} (on disk)", name),
+                    &format!("{} (from config)", name),
+                    &String::from_utf8_lossy(&current),
+                    contents,
+                )
+                .trim_end()
+                .to_string()
+            }
+            (Entry::Hardlink { source, .. }, Some(f)) if f != Found::Symlink => format!(
+                "{} is not hard linked to {}",
+                path.display(),
+                source.display()
+            ),
+            (Entry::Copy { source, .. }, Some(f)) if f != Found::Symlink => {
+                format!("{} differs from {}", path.display(), source.display())
+            }
+            (Entry::Directory { .. }, Some(_)) => format!("{} is not a directory", path.display()),
+            (Entry::Symlink { .. }, Some(_)) => format!("{} is not a symlink", path.display()),
+            (_, Some(_)) => format!("{} is not a file", path.display()),
+        })
+    }
+}
+
+/// What a path is, without following symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Found {
+    Directory,
+    File,
+    /// Symlink, dangling ones included
+    Symlink,
+}
+
+fn inspect(fs: &dyn FileSystem, path: &Path) -> ReconcileResult<Option<Found>> {
+    if fs.read_link(path).is_ok() {
+        return Ok(Some(Found::Symlink));
+    }
+
+    match fs.kind(path) {
+        Ok(FileKind::Directory) => Ok(Some(Found::Directory)),
+        Ok(_) => Ok(Some(Found::File)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(Inspect { path }),
+    }
+}
+
+fn is_up_to_date(
+    fs: &dyn FileSystem,
+    root: &Path,
+    path: &Path,
+    entry: &Entry,
+    found: Found,
+) -> ReconcileResult<bool> {
+    Ok(match entry {
+        Entry::Directory { .. } => found == Found::Directory,
+        Entry::Symlink { target, .. } => {
+            found == Found::Symlink && fs.read_link(path).context(Inspect { path })? == *target
+        }
+        Entry::File { contents, .. } => {
+            found == Found::File && fs.read(path).context(Inspect { path })? == contents.as_bytes()
+        }
+        Entry::Hardlink { source, .. } => {
+            found != Found::Symlink
+                && fs
+                    .is_hardlinked(&root.join(source), path)
+                    .context(Inspect { path })?
+        }
+        Entry::Copy { source, .. } => {
+            let source = root.join(source);
+            found != Found::Symlink
+                && fs.checksum(&source).context(Inspect { path: &source })?
+                    == fs.checksum(path).context(Inspect { path })?
+        }
+    })
+}
+
+/// Compares `plan` with the tree in `root` as `fs` sees it. `managed` are the top level paths
+/// the maker owns, anything below them that is not planned is removed, except:
+/// - contents of preserved directories (`log`, `mark`),
+/// - regular files, which are runtime output of the cores (syslog, pid, core dumps),
+/// - paths `owned` does not list, if the tree has a manifest.
+pub fn changes(
+    fs: &dyn FileSystem,
+    root: &Path,
+    plan: &Plan,
+    managed: &[PathBuf],
+    owned: Option<&BTreeSet<&Path>>,
+) -> ReconcileResult<Vec<Change>> {
+    let planned = plan
+        .create
+        .iter()
+        .map(|e| (e.path(), e))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut changes = vec![];
+
+    for path in managed.iter() {
+        collect_extra(fs, root, path, &planned, owned, &mut changes)?;
+    }
+
+    for e in plan.create.iter() {
+        let path = root.join(e.path());
+        match inspect(fs, &path)? {
+            None => changes.push(Change::Create(e.clone())),
+            Some(found) => {
+                if !is_up_to_date(fs, root, &path, e, found)? {
+                    changes.push(Change::Update(e.clone()))
+                }
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+fn collect_extra(
+    fs: &dyn FileSystem,
+    root: &Path,
+    path: &Path,
+    planned: &BTreeMap<&Path, &Entry>,
+    owned: Option<&BTreeSet<&Path>>,
+    changes: &mut Vec<Change>,
+) -> ReconcileResult<()> {
+    let full = root.join(path);
+    let found = match inspect(fs, &full)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    match planned.get(path) {
+        None => {
+            if found != Found::File && owned.is_none_or(|o| o.contains(path)) {
+                changes.push(Change::Remove(path.to_path_buf()))
+            }
+        }
+        Some(Entry::Directory {
+            preserve: false, ..
+        }) if found == Found::Directory => {
+            for (name, _) in fs.read_dir(&full).context(Inspect { path: &full })? {
+                collect_extra(fs, root, &path.join(name), planned, owned, changes)?;
+            }
+        }
+        Some(_) => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::process::{Process, Role};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum SupervisorError {
+    #[snafu(display("cannot register signal handler: {}", source))]
+    Signal { source: std::io::Error },
+    #[snafu(display("cannot resolve server directory {:?}: {}", path, source))]
+    Root {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("cannot start {:?}: {}", path, source))]
+    Spawn {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("cannot check {:?}: {}", path, source))]
+    Wait {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("db did not accept connections on port {} in time", port))]
+    DbTimeout { port: i64 },
+    #[snafu(display("db exited before accepting connections on port {}", port))]
+    DbExited { port: i64 },
+}
+
+type SupervisorResult<T, E = SupervisorError> = std::result::Result<T, E>;
+
+/// Something that happened to a supervised process, reported while `Supervisor::run` goes on.
+#[derive(Debug)]
+pub enum Event {
+    Started {
+        path: PathBuf,
+        pid: u32,
+    },
+    Exited {
+        path: PathBuf,
+        status: ExitStatus,
+        backoff: Duration,
+    },
+    /// Restarting failed, it is tried again after `backoff`
+    RestartFailed {
+        error: SupervisorError,
+        backoff: Duration,
+    },
+    /// Did not stop within `STOP_TIMEOUT` after SIGTERM
+    Killed {
+        path: PathBuf,
+    },
+    Stopped {
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Started { path, pid } => write!(f, "started {} (pid {})", path.display(), pid),
+            Event::Exited {
+                path,
+                status,
+                backoff,
+            } => write!(
+                f,
+                "{} exited ({}), restarting in {}s",
+                path.display(),
+                status,
+                backoff.as_secs()
+            ),
+            Event::RestartFailed { error, backoff } => {
+                write!(f, "{}, retrying in {}s", error, backoff.as_secs())
+            }
+            Event::Killed { path } => write!(f, "{} did not stop in time, killing", path.display()),
+            Event::Stopped { path } => write!(f, "stopped {}", path.display()),
+        }
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DB_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A process running at least this long before crashing starts over with the shortest backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+struct Supervised {
+    process: Process,
+    /// Absolute path of the binary symlink
+    path: PathBuf,
+    child: Option<Child>,
+    started: Instant,
+    failures: u32,
+    restart_at: Option<Instant>,
+}
+
+impl Supervised {
+    /// Schedules the next start with a backoff doubling per failure in a row.
+    fn schedule_restart(&mut self) -> Duration {
+        let backoff = Duration::from_secs(1 << self.failures.min(6)).min(MAX_BACKOFF);
+        self.failures += 1;
+        self.restart_at = Some(Instant::now() + backoff);
+
+        backoff
+    }
+}
+
+/// Runs the whole cluster in the foreground: db first, cores once db listens,
+/// crashed processes are restarted with backoff, SIGTERM/SIGINT stops everything
+/// in reverse order.
+pub struct Supervisor {
+    db_port: i64,
+    processes: Vec<Supervised>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Supervisor {
+    pub fn new(config: &Config, root: &Path) -> SupervisorResult<Self> {
+        let root = root.canonicalize().context(Root { path: root })?;
+
+        let processes = config
+            .processes()
+            .into_iter()
+            .map(|p| Supervised {
+                path: root.join(&p.dir).join(&p.binary),
+                process: p,
+                child: None,
+                started: Instant::now(),
+                failures: 0,
+                restart_at: None,
+            })
+            .collect();
+
+        Ok(Self {
+            db_port: config.db.bind_port,
+            processes,
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Runs until SIGTERM or SIGINT, passing every `Event` to `report` as it happens.
+    pub fn run<F: FnMut(Event)>(&mut self, mut report: F) -> SupervisorResult<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&self.stop))
+            .context(Signal)?;
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&self.stop))
+            .context(Signal)?;
+
+        let result = self
+            .start_all(&mut report)
+            .and_then(|_| self.watch(&mut report));
+        self.stop_all(&mut report);
+
+        result
+    }
+
+    fn start_all(&mut self, report: &mut dyn FnMut(Event)) -> SupervisorResult<()> {
+        for i in 0..self.processes.len() {
+            if self.stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            self.start(i, report)?;
+
+            if self.processes[i].process.role == Role::Db {
+                self.wait_for_db(i)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self, i: usize, report: &mut dyn FnMut(Event)) -> SupervisorResult<()> {
+        let s = &mut self.processes[i];
+        let child = Command::new(&s.path)
+            .current_dir(s.path.parent().unwrap())
+            .spawn()
+            .context(Spawn { path: &s.path })?;
+
+        report(Event::Started {
+            path: s.path.clone(),
+            pid: child.id(),
+        });
+        s.child = Some(child);
+        s.started = Instant::now();
+        s.restart_at = None;
+
+        Ok(())
+    }
+
+    fn wait_for_db(&mut self, i: usize) -> SupervisorResult<()> {
+        let addr = SocketAddr::from(([127, 0, 0, 1], self.db_port as u16));
+        let deadline = Instant::now() + DB_STARTUP_TIMEOUT;
+
+        while !self.stop.load(Ordering::SeqCst) {
+            if TcpStream::connect_timeout(&addr, POLL_INTERVAL).is_ok() {
+                return Ok(());
+            }
+
+            let s = &mut self.processes[i];
+            if let Some(ref mut child) = s.child {
+                if child.try_wait().context(Wait { path: &s.path })?.is_some() {
+                    return Err(SupervisorError::DbExited { port: self.db_port });
+                }
+            }
+
+            if Instant::now() > deadline {
+                return Err(SupervisorError::DbTimeout { port: self.db_port });
+            }
+            sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    fn watch(&mut self, report: &mut dyn FnMut(Event)) -> SupervisorResult<()> {
+        while !self.stop.load(Ordering::SeqCst) {
+            for i in 0..self.processes.len() {
+                let s = &mut self.processes[i];
+
+                if let Some(ref mut child) = s.child {
+                    if let Some(status) = child.try_wait().context(Wait { path: &s.path })? {
+                        if s.started.elapsed() >= STABLE_AFTER {
+                            s.failures = 0;
+                        }
+                        s.child = None;
+                        let backoff = s.schedule_restart();
+
+                        report(Event::Exited {
+                            path: s.path.clone(),
+                            status,
+                            backoff,
+                        });
+                    }
+                }
+
+                if matches!(s.restart_at, Some(at) if Instant::now() >= at) {
+                    // a failed respawn, e.g. while the binary is replaced, is retried later
+                    // instead of taking down the rest of the cluster
+                    if let Err(error) = self.start(i, report) {
+                        let backoff = self.processes[i].schedule_restart();
+                        report(Event::RestartFailed { error, backoff });
+                    }
+                }
+            }
+
+            sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    /// Terminates processes one by one in reverse start order, db last.
+    fn stop_all(&mut self, report: &mut dyn FnMut(Event)) {
+        for s in self.processes.iter_mut().rev() {
+            let mut child = match s.child.take() {
+                Some(v) => v,
+                None => continue,
+            };
+
+            unsafe {
+                libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+            }
+
+            let deadline = Instant::now() + STOP_TIMEOUT;
+            loop {
+                match child.try_wait() {
+                    Ok(None) if Instant::now() < deadline => sleep(POLL_INTERVAL),
+                    Ok(None) => {
+                        report(Event::Killed {
+                            path: s.path.clone(),
+                        });
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+
+            report(Event::Stopped {
+                path: s.path.clone(),
+            });
+        }
+    }
+}
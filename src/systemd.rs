@@ -0,0 +1,132 @@
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::process::{Process, Role};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum SystemdError {
+    #[snafu(display("cannot make directory {:?}: {}", path, source))]
+    CreateDirectory {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("cannot create file {:?}: {}", path, source))]
+    CreateFile {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+}
+
+type SystemdResult<T, E = SystemdError> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub struct Unit {
+    /// File name, e.g. `example-game1_2.service`
+    pub name: String,
+    pub contents: String,
+}
+
+fn unit_name(config: &Config, p: &Process) -> String {
+    let prefix = config.server_name.to_lowercase();
+    match p.role {
+        Role::Db => format!("{}-db.service", prefix),
+        Role::Game { setting, part } => format!(
+            "{}-game{}_{}.service",
+            prefix, config.channels.settings[setting].channel_id, part
+        ),
+        Role::Auth { id } => format!("{}-auth{}.service", prefix, id),
+    }
+}
+
+fn description(config: &Config, p: &Process) -> String {
+    match p.role {
+        Role::Db => format!("{} db", config.server_name),
+        Role::Game { setting, part } => format!(
+            "{} channel {} part {}",
+            config.server_name, config.channels.settings[setting].channel_id, part
+        ),
+        Role::Auth { id } => format!("{} auth {}", config.server_name, id),
+    }
+}
+
+/// One service per process plus `<server>.target` grouping them. Cores require the
+/// db unit and start after it, every service is restarted when it fails.
+pub fn units(config: &Config) -> Vec<Unit> {
+    let target = format!("{}.target", config.server_name.to_lowercase());
+    let home = config.home_dir();
+    let processes = config.processes();
+    let db = unit_name(config, &processes[0]);
+
+    let mut units = processes
+        .iter()
+        .map(|p| {
+            let dependencies = match p.role {
+                Role::Db => String::new(),
+                _ => format!("After={0}\nRequires={0}\n", db),
+            };
+            let dir = format!("{}/{}", home, p.dir.display());
+
+            Unit {
+                name: unit_name(config, p),
+                contents: format!(
+                    "[Unit]
+Description={}
+PartOf={}
+{}
+[Service]
+Type=simple
+WorkingDirectory={}
+ExecStart={}/{}
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy={}
+",
+                    description(config, p),
+                    target,
+                    dependencies,
+                    dir,
+                    dir,
+                    p.binary,
+                    target
+                ),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    units.push(Unit {
+        contents: format!(
+            "[Unit]
+Description={} server
+Wants={}
+
+[Install]
+WantedBy=multi-user.target
+",
+            config.server_name,
+            units
+                .iter()
+                .map(|u| u.name.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        name: target,
+    });
+
+    units
+}
+
+/// Writes `units` into `dir`, normally `/etc/systemd/system`.
+pub fn install(units: &[Unit], dir: &Path) -> SystemdResult<()> {
+    create_dir_all(dir).context(CreateDirectory { path: dir })?;
+
+    for u in units.iter() {
+        let path = dir.join(&u.name);
+        fs::write(&path, &u.contents).context(CreateFile { path })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum TemplateError {
+    #[snafu(display("cannot read template {:?}: {}", path, source))]
+    Read {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("template {} line {}: unclosed {{{{", template, line))]
+    Unclosed { template: String, line: usize },
+    #[snafu(display("template {} line {}: unknown variable {:?}", template, line, name))]
+    Unknown {
+        template: String,
+        line: usize,
+        name: String,
+    },
+}
+
+type TemplateResult<T, E = TemplateError> = std::result::Result<T, E>;
+
+pub type Variables = BTreeMap<&'static str, String>;
+
+pub const GAME_CONFIG: &str = "game.CONFIG";
+pub const AUTH_CONFIG: &str = "auth.CONFIG";
+pub const DB_CONF: &str = "db.conf.txt";
+
+lazy_static! {
+    static ref BUILTIN: Vec<(&'static str, &'static str)> = vec![
+        (GAME_CONFIG, include_str!("../templates/game.CONFIG")),
+        (AUTH_CONFIG, include_str!("../templates/auth.CONFIG")),
+        (DB_CONF, include_str!("../templates/db.conf.txt")),
+    ];
+}
+
+/// Sources of the generated config files. Variables are written as `{{ name }}`.
+#[derive(Debug)]
+pub struct Templates {
+    sources: BTreeMap<&'static str, String>,
+}
+
+impl Templates {
+    /// Built-in templates, each one replaced by the file with the same name in `dir` if it exists.
+    pub fn load(dir: Option<&Path>) -> TemplateResult<Self> {
+        let mut sources = BTreeMap::new();
+
+        for (name, builtin) in BUILTIN.iter() {
+            let source = match dir.map(|d| d.join(name)) {
+                Some(path) if path.is_file() => read_to_string(&path).context(Read { path })?,
+                _ => builtin.to_string(),
+            };
+            sources.insert(*name, source);
+        }
+
+        Ok(Self { sources })
+    }
+
+    pub fn render(&self, name: &str, variables: &Variables) -> TemplateResult<String> {
+        let source = &self.sources[name];
+        let mut out = String::with_capacity(source.len());
+
+        for (i, line) in source.split_inclusive('\n').enumerate() {
+            let mut rest = line;
+            while let Some(start) = rest.find("{{") {
+                out.push_str(&rest[..start]);
+
+                let end = rest[start..]
+                    .find("}}")
+                    .ok_or_else(|| TemplateError::Unclosed {
+                        template: name.to_string(),
+                        line: i + 1,
+                    })?;
+                let variable = rest[start + 2..start + end].trim();
+
+                match variables.get(variable) {
+                    Some(v) => out.push_str(v),
+                    None => {
+                        return Err(TemplateError::Unknown {
+                            template: name.to_string(),
+                            line: i + 1,
+                            name: variable.to_string(),
+                        })
+                    }
+                }
+
+                rest = &rest[start + end + 2..];
+            }
+            out.push_str(rest);
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use crate::config::{extra_value, Config, Extra};
+use crate::filesystem::FileSystem;
+use crate::process::Role;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    /// JSON path of the offending field, e.g. `channels.settings[2].p2p_port`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.path, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    fn push(&mut self, severity: Severity, path: String, message: String) {
+        self.problems.push(Problem {
+            severity,
+            path,
+            message,
+        })
+    }
+
+    pub fn error(&mut self, path: String, message: String) {
+        self.push(Severity::Error, path, message)
+    }
+
+    pub fn warning(&mut self, path: String, message: String) {
+        self.push(Severity::Warning, path, message)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, p) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", p)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks everything that parses fine but would generate a broken tree.
+/// All problems are collected, nothing stops at the first one. Map indexes are checked
+/// against the map index in the share directory, read through `fs`.
+pub fn validate(config: &Config, fs: &dyn FileSystem, root: &Path) -> Report {
+    let mut report = Report::default();
+
+    check_install_dir(config, &mut report);
+    check_channels(config, &mut report);
+    check_ports(config, &mut report);
+    check_balance(config, &mut report);
+    check_maps(config, fs, root, &mut report);
+    check_db(config, &mut report);
+    check_extra(config, &mut report);
+
+    report
+}
+
+fn check_install_dir(config: &Config, report: &mut Report) {
+    if let Some(ref dir) = config.install_dir {
+        if dir.is_relative() {
+            report.error(
+                "install_dir".to_string(),
+                format!("{} is not an absolute path", dir.display()),
+            );
+        }
+    }
+}
+
+fn check_channels(config: &Config, report: &mut Report) {
+    let mut ids: HashMap<i64, String> = HashMap::new();
+    let mut dirs: HashMap<String, String> = HashMap::new();
+
+    for (i, r) in config.channels.ranges.iter().enumerate() {
+        if r.from > r.to {
+            report.error(
+                format!("channels.ranges[{}]", i),
+                format!("from {} is greater than to {}", r.from, r.to),
+            );
+        }
+    }
+
+    // settings expanded from ranges come after the ones in the file
+    for (i, s) in config.channels.settings.iter().enumerate() {
+        let path = config.setting_path(i, "channel_id", None);
+        match ids.get(&s.channel_id) {
+            Some(first) => report.error(
+                path,
+                format!("channel id {} is already used by {}", s.channel_id, first),
+            ),
+            None => {
+                ids.insert(s.channel_id, path);
+            }
+        }
+
+        let path = config.setting_path(i, "", None);
+        let dir = s.channel_dir_name().into_owned();
+        match dirs.get(&dir) {
+            Some(first) => report.error(
+                path,
+                format!("directory {:?} is already used by {}", dir, first),
+            ),
+            None => {
+                dirs.insert(dir, path);
+            }
+        }
+    }
+
+    if config.auth.ports.is_empty() {
+        report.warning(
+            "auth.ports".to_string(),
+            "no auth instances are configured".to_string(),
+        );
+    }
+}
+
+fn check_ports(config: &Config, report: &mut Report) {
+    if let Some(ref ranges) = config.port_ranges {
+        let named = [
+            ("game", ranges.game),
+            ("p2p", ranges.p2p),
+            ("auth", ranges.auth),
+        ];
+        for (name, range) in named.iter() {
+            let range = match range {
+                Some(v) => v,
+                None => continue,
+            };
+            if range.start < 1 || range.end > 65535 || range.start > range.end {
+                report.error(
+                    format!("port_ranges.{}", name),
+                    format!("{}-{} is not a valid port range", range.start, range.end),
+                );
+            }
+        }
+    }
+
+    // settings expanded from a range share its stride, that one is checked once
+    let mut strides = vec![(
+        "channels.port_stride".to_string(),
+        config.channels.port_stride,
+    )];
+    for (i, s) in config.channels.settings.iter().enumerate() {
+        if let (None, Some(stride)) = (s.range, s.port_stride) {
+            strides.push((format!("channels.settings[{}].port_stride", i), stride));
+        }
+    }
+    for (i, r) in config.channels.ranges.iter().enumerate() {
+        if let Some(stride) = r.port_stride {
+            strides.push((format!("channels.ranges[{}].port_stride", i), stride));
+        }
+    }
+    for (path, stride) in strides {
+        if !(1..=65535).contains(&stride) {
+            report.error(
+                path,
+                format!("stride {} is not between 1 and 65535", stride),
+            );
+        }
+    }
+
+    // every port a process listens on, as resolved for the generated CONFIG files
+    let mut ports: Vec<(String, i64)> = vec![];
+
+    for p in config.processes().iter() {
+        match p.role {
+            Role::Db => ports.push(("db.bind_port".to_string(), p.port)),
+            Role::Game { setting, part } => {
+                let (port, p2p_port) = config.part_ports(setting, part);
+                ports.push((port.path, port.value));
+                ports.push((p2p_port.path, p2p_port.value));
+            }
+            Role::Auth { id } => {
+                let (port, p2p_port) = config.auth_ports(id);
+                ports.push((port.path, port.value));
+                ports.push((p2p_port.path, p2p_port.value));
+            }
+        }
+    }
+
+    let mut used: HashMap<i64, &str> = HashMap::new();
+    for (path, port) in ports.iter() {
+        if *port < 1 || *port > 65535 {
+            report.error(path.clone(), format!("port {} is out of range", port));
+            continue;
+        }
+
+        match used.get(port) {
+            Some(first) => report.error(
+                path.clone(),
+                format!("port {} is already used by {}", port, first),
+            ),
+            None => {
+                used.insert(*port, path);
+            }
+        }
+    }
+}
+
+fn check_balance(config: &Config, report: &mut Report) {
+    let balance = match config.channels.balance {
+        Some(ref v) => v,
+        None => return,
+    };
+
+    if balance.parts == 0 {
+        report.error(
+            "channels.balance.parts".to_string(),
+            "at least one part is needed".to_string(),
+        );
+    }
+
+    let mut seen: HashMap<i64, String> = HashMap::new();
+    for (i, m) in balance.maps.iter().enumerate() {
+        let path = format!("channels.balance.maps[{}]", i);
+
+        if m.weight < 0 {
+            report.error(
+                format!("{}.weight", path),
+                format!("weight {} is negative", m.weight),
+            );
+        }
+        if let Some(part) = m.part {
+            if part < 1 || part > balance.parts {
+                report.error(
+                    format!("{}.part", path),
+                    format!("part {} does not exist", part),
+                );
+            }
+        }
+
+        match seen.get(&m.map) {
+            Some(first) => report.error(
+                path.clone(),
+                format!("map {} is already listed in {}", m.map, first),
+            ),
+            None => {
+                seen.insert(m.map, path);
+            }
+        }
+    }
+}
+
+/// Map indexes listed in `<share>/locale/<locale_service>/map/index`, `None` if it cannot be read.
+fn read_map_index(fs: &dyn FileSystem, share: &Path, locale: &str) -> Option<BTreeSet<i64>> {
+    let data = fs
+        .read(&share.join("locale").join(locale).join("map/index"))
+        .ok()?;
+    let data = String::from_utf8_lossy(&data);
+
+    Some(
+        data.lines()
+            .filter_map(|l| l.split_whitespace().next())
+            .filter_map(|v| v.parse().ok())
+            .collect(),
+    )
+}
+
+fn check_maps(config: &Config, fs: &dyn FileSystem, root: &Path, report: &mut Report) {
+    let share = config.share.dir(root);
+    let mut indexes: BTreeMap<String, Option<BTreeSet<i64>>> = BTreeMap::new();
+    // common_maps are shared by channels, report each of their problems once
+    let mut reported: HashSet<(String, i64)> = HashSet::new();
+
+    for (i, x) in config.channels.settings.iter().enumerate() {
+        let mut hosted: BTreeMap<i64, usize> = BTreeMap::new();
+        let mut known: BTreeSet<i64> = BTreeSet::new();
+
+        for (part, definition) in (1..).zip(x.get_parts(&config.channels).iter()) {
+            let locale = config.part_common(x, part).locale_service;
+            let index = indexes.entry(locale.clone()).or_insert_with(|| {
+                let index = read_map_index(fs, &share, &locale);
+                if index.is_none() {
+                    report.warning(
+                        "common.locale_service".to_string(),
+                        format!(
+                            "cannot read {}/locale/{}/map/index, maps are not checked",
+                            config.share.path.display(),
+                            locale
+                        ),
+                    );
+                }
+                index
+            });
+            let index = match index {
+                Some(v) => v,
+                None => continue,
+            };
+            known.extend(index.iter());
+
+            let path = config.part_path(i, part, "maps");
+            for m in definition.maps.iter() {
+                if !index.contains(m) && reported.insert((path.clone(), *m)) {
+                    report.error(
+                        path.clone(),
+                        format!("map {} is not in the {} map index", m, locale),
+                    );
+                }
+
+                match hosted.get(m) {
+                    Some(first) => report.warning(
+                        config.setting_path(i, "", None),
+                        format!("map {} is allowed on part {} and part {}", m, first, part),
+                    ),
+                    None => {
+                        hosted.insert(*m, part);
+                    }
+                }
+            }
+        }
+
+        let missing = known
+            .iter()
+            .filter(|m| !hosted.contains_key(m))
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            report.warning(
+                config.setting_path(i, "", None),
+                format!("maps {} are allowed on no part", missing.join(" ")),
+            );
+        }
+    }
+}
+
+fn check_db(config: &Config, report: &mut Report) {
+    let range = &config.db.item_id_range;
+    if range.start > range.end {
+        report.error(
+            "db.item_id_range".to_string(),
+            format!("start {} is greater than end {}", range.start, range.end),
+        );
+    }
+}
+
+fn check_extra_keys(path: &str, extra: &Extra, report: &mut Report) {
+    for (k, v) in extra.iter() {
+        if k.is_empty() || k.contains(|c: char| c.is_whitespace() || c == ':' || c == '=') {
+            report.error(
+                format!("{}.extra", path),
+                format!("{:?} is not a valid key", k),
+            );
+        }
+        if extra_value(v).is_none() {
+            report.error(
+                format!("{}.extra.{}", path, k),
+                "value has to be a string without line breaks, number or boolean".to_string(),
+            );
+        }
+    }
+}
+
+fn check_extra(config: &Config, report: &mut Report) {
+    check_extra_keys("common", &config.common.extra, report);
+    check_extra_keys("auth", &config.auth.extra, report);
+    check_extra_keys("db", &config.db.extra, report);
+
+    for (i, p) in config.auth.ports.iter().enumerate() {
+        check_extra_keys(&format!("auth.ports[{}]", i), &p.extra, report);
+    }
+
+    // settings expanded from a range share its extra keys, those are checked once below
+    for (i, s) in config.channels.settings.iter().enumerate() {
+        if s.range.is_some() {
+            continue;
+        }
+        let path = format!("channels.settings[{}]", i);
+        check_extra_keys(&path, &s.extra, report);
+
+        for (j, part) in s.override_maps.iter().flatten().enumerate() {
+            check_extra_keys(
+                &format!("{}.override_maps[{}]", path, j),
+                &part.extra,
+                report,
+            );
+        }
+    }
+
+    for (i, r) in config.channels.ranges.iter().enumerate() {
+        let path = format!("channels.ranges[{}]", i);
+        check_extra_keys(&path, &r.extra, report);
+
+        for (j, part) in r.override_maps.iter().flatten().enumerate() {
+            check_extra_keys(
+                &format!("{}.override_maps[{}]", path, j),
+                &part.extra,
+                report,
+            );
+        }
+    }
+
+    for (i, part) in config.channels.common_maps.iter().enumerate() {
+        check_extra_keys(&format!("channels.common_maps[{}]", i), &part.extra, report);
+    }
+}
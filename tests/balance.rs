@@ -0,0 +1,56 @@
+use channels_maker::config::MapBalance;
+use serde_json::json;
+
+fn balance(value: serde_json::Value) -> MapBalance {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn heaviest_maps_go_to_the_least_loaded_part() {
+    let b = balance(json!({
+        "parts": 2,
+        "maps": [
+            {"map": 4, "weight": 2},
+            {"map": 1, "weight": 5},
+            {"map": 2, "weight": 3},
+            {"map": 3, "weight": 3}
+        ]
+    }));
+
+    let parts = b.split();
+    assert_eq!(parts, vec![vec![1, 4], vec![2, 3]]);
+    assert_eq!(b.load(&parts[0]), 7);
+    assert_eq!(b.load(&parts[1]), 6);
+}
+
+#[test]
+fn ties_go_to_the_lower_map_and_part() {
+    let b = balance(json!({"parts": 2, "maps": [4, 3, 2, 1]}));
+
+    assert_eq!(b.split(), vec![vec![1, 3], vec![2, 4]]);
+}
+
+#[test]
+fn pinned_maps_stay_on_their_part() {
+    let b = balance(json!({
+        "parts": 2,
+        "maps": [
+            {"map": 1, "weight": 10, "part": 2},
+            {"map": 2, "weight": 4},
+            {"map": 3, "weight": 4}
+        ]
+    }));
+
+    // part 2 starts with the load of map 1, so both free maps fit on part 1 first
+    assert_eq!(b.split(), vec![vec![2, 3], vec![1]]);
+}
+
+#[test]
+fn maps_pinned_to_a_missing_part_are_balanced() {
+    let b = balance(json!({
+        "parts": 2,
+        "maps": [{"map": 1, "part": 3}, {"map": 2, "part": 0}, {"map": 3, "part": 1}]
+    }));
+
+    assert_eq!(b.split(), vec![vec![2, 3], vec![1]]);
+}
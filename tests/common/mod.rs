@@ -0,0 +1,87 @@
+//! Fixtures shared by the integration tests.
+
+// every test crate includes this module, but none of them uses all of it
+#![allow(dead_code)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use channels_maker::filesystem::Node;
+use channels_maker::{Cleanup, Config, Maker, MemoryFileSystem, CONFIG_FILE};
+
+const SHARE_DIRECTORIES: [&str; 3] = ["data", "package", "locale"];
+const SHARE_FILES: [&str; 5] = [
+    "CMD",
+    "item_proto.txt",
+    "item_names.txt",
+    "mob_proto.txt",
+    "mob_names.txt",
+];
+const SHARE_BINARIES: [&str; 2] = ["db", "game_example"];
+
+/// Everything the example config links to in `share/`, created below `root` on disk.
+pub fn share(root: &Path) {
+    let share = root.join("share");
+    for dir in SHARE_DIRECTORIES.iter() {
+        fs::create_dir_all(share.join(dir)).unwrap();
+    }
+    for file in SHARE_FILES.iter() {
+        fs::write(share.join(file), "").unwrap();
+    }
+    for binary in SHARE_BINARIES.iter() {
+        let path = share.join(binary);
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+/// Like `share`, in `fs`.
+pub fn share_in_memory(fs: &MemoryFileSystem, root: &Path) {
+    let share = root.join("share");
+    for dir in SHARE_DIRECTORIES.iter() {
+        fs.insert(share.join(dir), Node::Directory);
+    }
+    for file in SHARE_FILES.iter() {
+        fs.insert(
+            share.join(file),
+            Node::File {
+                contents: vec![],
+                executable: false,
+            },
+        );
+    }
+    for binary in SHARE_BINARIES.iter() {
+        fs.insert(
+            share.join(binary),
+            Node::File {
+                contents: vec![],
+                executable: true,
+            },
+        );
+    }
+}
+
+/// Maker for the server directory `root`, with the config read from there.
+pub fn maker(root: &Path) -> Maker {
+    let config_path = root.join(CONFIG_FILE);
+    let config = Config::read_config(&config_path).unwrap();
+    Maker::new(config, root.to_path_buf(), &config_path, None).unwrap()
+}
+
+/// A tree freshly generated from the example config, `edit`ed afterwards.
+pub fn generated<F: FnOnce(&Path)>(edit: F) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    share(root);
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("config.example.json"),
+        root.join(CONFIG_FILE),
+    )
+    .unwrap();
+    maker(root).make(Cleanup::Refuse).unwrap();
+
+    edit(root);
+
+    dir
+}
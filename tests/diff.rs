@@ -0,0 +1,119 @@
+//! `diff` on a generated tree that was edited by hand afterwards.
+
+use std::fs;
+
+use channels_maker::diff::unified;
+use channels_maker::manifest::MANIFEST_FILE;
+use channels_maker::CONFIG_FILE;
+
+mod common;
+
+use common::{generated, maker};
+
+#[test]
+fn diff_shows_hand_edits() {
+    let dir = generated(|root| {
+        let path = root.join("channel1/part2/CONFIG");
+        let config = fs::read_to_string(&path).unwrap();
+        fs::write(&path, config.replace("PORT: 61001\n", "PORT: 7000\n")).unwrap();
+        fs::remove_dir(root.join("channel2/part1/mark")).unwrap();
+        fs::remove_file(root.join("db/data")).unwrap();
+        std::os::unix::fs::symlink("/tmp", root.join("db/data")).unwrap();
+        // runtime output of the cores and paths the manifest does not list are not drift
+        fs::write(root.join("channel1/part1/syslog"), "").unwrap();
+        fs::create_dir(root.join("channel1/part1/old")).unwrap();
+    });
+
+    let diff = maker(dir.path()).diff().unwrap();
+    assert_eq!(
+        diff,
+        vec![
+            [
+                "--- channel1/part2/CONFIG (on disk)",
+                "+++ channel1/part2/CONFIG (from config)",
+                "@@ -1,6 +1,6 @@",
+                " CHANNEL: 1",
+                " HOSTNAME: part2",
+                "-PORT: 7000",
+                "+PORT: 61001",
+                " P2P_PORT: 62001",
+                " DB_ADDR: 127.0.0.1",
+                " DB_PORT: 3306",
+            ]
+            .join("\n"),
+            "missing directory channel2/part1/mark".to_string(),
+            "db/data -> /tmp, expected ../share/data".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn diff_lists_what_the_config_does_not_describe_anymore() {
+    let dir = generated(|root| {
+        let path = root.join(CONFIG_FILE);
+        let mut config: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        config["channels"]["settings"]
+            .as_array_mut()
+            .unwrap()
+            .retain(|s| s["channel_id"] != 99);
+        fs::write(&path, config.to_string()).unwrap();
+    });
+
+    let diff = maker(dir.path()).diff().unwrap();
+    assert!(diff.contains(&"extra channel99".to_string()), "{:?}", diff);
+    assert!(
+        diff.iter().any(|d| d.starts_with("--- start.sh")),
+        "{:?}",
+        diff
+    );
+    assert!(
+        !diff.iter().any(|d| d.contains(MANIFEST_FILE)),
+        "{:?}",
+        diff
+    );
+}
+
+#[test]
+fn fresh_tree_has_no_diff() {
+    let dir = generated(|_| {});
+    assert_eq!(maker(dir.path()).diff().unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn unified_diff_merges_close_changes_into_one_hunk() {
+    let old = (1..=20).map(|n| format!("{}\n", n)).collect::<String>();
+    let new = (1..=21)
+        .filter(|n| *n != 9)
+        .map(|n| match n {
+            5 => "five\n".to_string(),
+            n => format!("{}\n", n),
+        })
+        .collect::<String>();
+
+    assert_eq!(
+        unified("a", "b", &old, &new),
+        "--- a
++++ b
+@@ -2,11 +2,10 @@
+ 2
+ 3
+ 4
+-5
++five
+ 6
+ 7
+ 8
+-9
+ 10
+ 11
+ 12
+@@ -18,3 +17,4 @@
+ 18
+ 19
+ 20
++21
+"
+    );
+    assert_eq!(unified("a", "b", &old, &old), "");
+}
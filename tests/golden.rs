@@ -0,0 +1,181 @@
+//! Generates trees from the configs in `fixtures/` and compares them with `golden/`.
+//! Run with `UPDATE_GOLDEN=1` to rewrite the snapshots after an intended change.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use channels_maker::manifest::{self, Manifest, ManifestEntry, MANIFEST_FILE};
+use channels_maker::{Cleanup, Config, Maker, CONFIG_FILE};
+
+mod common;
+
+use common::share;
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Every entry below `dir` except `share/`, the config and the manifest, one per line, files
+/// followed by their contents. The manifest holds hashes of the config and every file, it is
+/// checked by `manifest_lists_every_generated_path` instead.
+fn snapshot(root: &Path, dir: &Path, out: &mut String) {
+    let mut entries = fs::read_dir(root.join(dir))
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    for name in entries {
+        let rel = dir.join(&name);
+        if rel == Path::new("share")
+            || rel == Path::new(CONFIG_FILE)
+            || rel == Path::new(MANIFEST_FILE)
+        {
+            continue;
+        }
+
+        let path = root.join(&rel);
+        let meta = fs::symlink_metadata(&path).unwrap();
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(&path).unwrap();
+            writeln!(out, "{} -> {}", rel.display(), target.display()).unwrap();
+        } else if meta.is_dir() {
+            writeln!(out, "{}/", rel.display()).unwrap();
+            snapshot(root, &rel, out);
+        } else {
+            writeln!(out, "{}:", rel.display()).unwrap();
+            for line in fs::read_to_string(&path).unwrap().lines() {
+                writeln!(out, "| {}", line).unwrap();
+            }
+        }
+    }
+}
+
+/// Tree generated from `config` in a new temporary directory.
+fn generate(config: &Path) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    share(root);
+    fs::copy(config, root.join(CONFIG_FILE)).unwrap();
+
+    let config_path = root.join(CONFIG_FILE);
+    let config = Config::read_config(&config_path).unwrap();
+    Maker::new(config, root.to_path_buf(), &config_path, None)
+        .unwrap()
+        .make(Cleanup::Refuse)
+        .unwrap();
+
+    dir
+}
+
+fn check(name: &str, config: &Path) {
+    let dir = generate(config);
+    let root = dir.path();
+
+    let mut actual = String::new();
+    snapshot(root, Path::new(""), &mut actual);
+
+    let golden = manifest_dir()
+        .join("tests/golden")
+        .join(format!("{}.txt", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|_| panic!("{:?} is missing, run with UPDATE_GOLDEN=1", golden));
+    if actual != expected {
+        let line = actual
+            .lines()
+            .zip(expected.lines())
+            .position(|(a, e)| a != e)
+            .unwrap_or_else(|| actual.lines().count().min(expected.lines().count()));
+        panic!(
+            "{} differs from {:?} at line {}:\n  actual:   {:?}\n  expected: {:?}\nrun with UPDATE_GOLDEN=1 if this is intended",
+            name,
+            golden,
+            line + 1,
+            actual.lines().nth(line),
+            expected.lines().nth(line)
+        );
+    }
+}
+
+fn fixture(name: &str) {
+    check(
+        name,
+        &manifest_dir()
+            .join("tests/fixtures")
+            .join(format!("{}.json", name)),
+    );
+}
+
+#[test]
+fn example() {
+    check("example", &manifest_dir().join("config.example.json"));
+}
+
+#[test]
+fn renamed() {
+    fixture("renamed");
+}
+
+#[test]
+fn empty_override_maps() {
+    fixture("empty_override_maps");
+}
+
+#[test]
+fn multiple_auth() {
+    fixture("multiple_auth");
+}
+
+#[test]
+fn part_objects() {
+    fixture("part_objects");
+}
+
+#[test]
+fn manifest_lists_every_generated_path() {
+    let dir = generate(&manifest_dir().join("config.example.json"));
+    let root = dir.path();
+    let manifest = fs::read(root.join(MANIFEST_FILE)).unwrap();
+    let manifest = Manifest::parse(&manifest, &root.join(MANIFEST_FILE)).unwrap();
+    let config = Config::read_config(&root.join(CONFIG_FILE)).unwrap();
+    assert_eq!(manifest.config_hash, manifest::config_hash(&config));
+
+    let mut snapshot_paths = String::new();
+    snapshot(root, Path::new(""), &mut snapshot_paths);
+    let generated = snapshot_paths
+        .lines()
+        .filter(|l| !l.starts_with("| "))
+        .map(|l| {
+            let path = l.split(" -> ").next().unwrap();
+            path.trim_end_matches(['/', ':']).to_string()
+        })
+        .collect::<Vec<_>>();
+    let mut listed = manifest
+        .entries
+        .iter()
+        .map(|e| e.path().display().to_string())
+        .collect::<Vec<_>>();
+    listed.sort();
+    let mut expected = generated.clone();
+    expected.sort();
+    assert_eq!(listed, expected);
+
+    for e in manifest.entries.iter() {
+        if let ManifestEntry::File { path, sha256 } = e {
+            let contents = fs::read(root.join(path)).unwrap();
+            assert_eq!(
+                *sha256,
+                channels_maker::deploy::sha256(&contents),
+                "{:?}",
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,638 @@
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use channels_maker::config::DeployMode;
+use channels_maker::filesystem::{FileKind, Node};
+use channels_maker::maker::MakerError;
+use channels_maker::manifest::MANIFEST_FILE;
+use channels_maker::{Cleanup, Config, FileSystem, Maker, MemoryFileSystem};
+
+mod common;
+
+const ROOT: &str = "/srv/example";
+
+fn config() -> Config {
+    Config::parse(include_str!("../config.example.json")).unwrap()
+}
+
+fn share(fs: &MemoryFileSystem) {
+    common::share_in_memory(fs, Path::new(ROOT));
+}
+
+fn maker(fs: &Rc<MemoryFileSystem>) -> Maker {
+    maker_with(fs, config())
+}
+
+fn maker_with(fs: &Rc<MemoryFileSystem>, config: Config) -> Maker {
+    Maker::with_file_system(
+        config,
+        PathBuf::from(ROOT),
+        &Path::new(ROOT).join("config.json"),
+        None,
+        Box::new(Rc::clone(fs)),
+    )
+    .unwrap()
+}
+
+/// Fails the first write, symlink or rename whose destination ends with `fail_at`.
+#[derive(Debug)]
+struct Failing {
+    fs: Rc<MemoryFileSystem>,
+    fail_at: &'static str,
+    failed: Cell<bool>,
+}
+
+impl Failing {
+    fn check(&self, path: &Path) -> io::Result<()> {
+        if path.ends_with(self.fail_at) && !self.failed.replace(true) {
+            return Err(io::Error::other("injected failure"));
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for Failing {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(OsString, Option<FileKind>)>> {
+        self.fs.read_dir(dir)
+    }
+
+    fn kind(&self, path: &Path) -> io::Result<FileKind> {
+        self.fs.kind(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.fs.read(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.fs.read_link(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.fs.create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.fs.create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.check(path)?;
+        self.fs.write(path, contents)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        self.check(path)?;
+        self.fs.symlink(target, path)
+    }
+
+    fn hardlink(&self, source: &Path, path: &Path) -> io::Result<()> {
+        self.fs.hardlink(source, path)
+    }
+
+    fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+        self.fs.copy(source, path)
+    }
+
+    fn is_hardlinked(&self, source: &Path, path: &Path) -> io::Result<bool> {
+        self.fs.is_hardlinked(source, path)
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<String> {
+        self.fs.checksum(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.fs.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check(to)?;
+        self.fs.rename(from, to)
+    }
+}
+
+/// A tree from an earlier run with a hand-edited CONFIG, a log and a file that is not part of
+/// the layout.
+fn existing(fs: &Rc<MemoryFileSystem>) {
+    share(fs);
+    maker(fs).make(Cleanup::Refuse).unwrap();
+    fs.insert(
+        Path::new(ROOT).join("channel1/part1/log/syslog"),
+        Node::File {
+            contents: b"booted".to_vec(),
+            executable: false,
+        },
+    );
+    fs.insert(
+        Path::new(ROOT).join("notes.txt"),
+        Node::File {
+            contents: b"keep me".to_vec(),
+            executable: false,
+        },
+    );
+    fs.write(&Path::new(ROOT).join("channel1/part1/CONFIG"), b"old")
+        .unwrap();
+}
+
+/// `existing` as made by a version without a manifest, where every entry that is not
+/// whitelisted is in the way.
+fn existing_without_manifest(fs: &Rc<MemoryFileSystem>) {
+    existing(fs);
+    fs.remove(&Path::new(ROOT).join(MANIFEST_FILE)).unwrap();
+}
+
+fn failing_maker(fs: &Rc<MemoryFileSystem>, fail_at: &'static str) -> Maker {
+    Maker::with_file_system(
+        config(),
+        PathBuf::from(ROOT),
+        &Path::new(ROOT).join("config.json"),
+        None,
+        Box::new(Failing {
+            fs: Rc::clone(fs),
+            fail_at,
+            failed: Cell::new(false),
+        }),
+    )
+    .unwrap()
+}
+
+fn file(fs: &MemoryFileSystem, path: &str) -> String {
+    match fs.get(Path::new(ROOT).join(path)) {
+        Some(Node::File { contents, .. }) => String::from_utf8(contents).unwrap(),
+        other => panic!("{} is {:?}", path, other),
+    }
+}
+
+#[test]
+fn make_writes_the_whole_layout_in_memory() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    share(&fs);
+
+    maker(&fs).make(Cleanup::Refuse).unwrap();
+
+    let tree = fs.tree(ROOT);
+    for dir in ["db", "auth/1/log", "channel1/part2/mark", "channel99/part1"].iter() {
+        assert_eq!(tree.get(Path::new(dir)), Some(&Node::Directory), "{}", dir);
+    }
+    assert_eq!(
+        tree.get(Path::new("channel1/part1/data")),
+        Some(&Node::Symlink(PathBuf::from("../../share/data")))
+    );
+    assert_eq!(
+        tree.get(Path::new("db/db_example")),
+        Some(&Node::Symlink(PathBuf::from("../share/db")))
+    );
+
+    let part2 = file(&fs, "channel1/part2/CONFIG");
+    assert!(part2.contains("PORT: 61001\n"), "{}", part2);
+    assert!(part2.contains("P2P_PORT: 62001\n"), "{}", part2);
+    assert!(file(&fs, "start.sh").starts_with("#!/bin/sh\n"));
+
+    assert!(!Path::new(ROOT).exists());
+}
+
+#[test]
+fn layout_matches_what_make_writes() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    share(&fs);
+    let maker = maker(&fs);
+
+    let planned = maker.layout().unwrap().create.len();
+    maker.make(Cleanup::Refuse).unwrap();
+
+    // the planned entries plus share/ and what is in it
+    let share_entries = fs.tree(Path::new(ROOT).join("share")).len() + 1;
+    assert_eq!(fs.tree(ROOT).len(), planned + share_entries);
+}
+
+#[test]
+fn make_reports_incomplete_share() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    fs.insert(Path::new(ROOT).join("share/data"), Node::Directory);
+
+    match maker(&fs).make(Cleanup::Refuse) {
+        Err(MakerError::Share { problems }) => {
+            assert!(problems.contains(&"share/db: missing".to_string()));
+            assert!(!problems.iter().any(|p| p.starts_with("share/data")));
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(fs.tree(ROOT).len(), 2);
+}
+
+#[test]
+fn make_replaces_only_what_the_manifest_lists() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+
+    // no --force needed, notes.txt is neither generated nor in the way
+    maker(&fs).make(Cleanup::Refuse).unwrap();
+
+    assert!(file(&fs, "channel1/part1/CONFIG").contains("PORT: 61000\n"));
+    assert_eq!(file(&fs, "channel1/part1/log/syslog"), "booted");
+    assert_eq!(file(&fs, "notes.txt"), "keep me");
+    assert!(!fs
+        .tree(ROOT)
+        .keys()
+        .any(|p| p.starts_with(".channels-maker.staging")
+            || p.starts_with(".channels-maker.previous")));
+}
+
+#[test]
+fn make_without_a_manifest_removes_what_is_in_the_way() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing_without_manifest(&fs);
+
+    match maker(&fs).make(Cleanup::Refuse) {
+        Err(MakerError::NotEmpty) => {}
+        other => panic!("{:?}", other),
+    }
+    maker(&fs).make(Cleanup::Remove).unwrap();
+
+    assert!(file(&fs, "channel1/part1/CONFIG").contains("PORT: 61000\n"));
+    assert_eq!(fs.get(Path::new(ROOT).join("notes.txt")), None);
+    assert!(fs.get(Path::new(ROOT).join(MANIFEST_FILE)).is_some());
+}
+
+#[test]
+fn make_removes_paths_the_config_does_not_describe_anymore() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+
+    let mut config = config();
+    config.channels.settings.retain(|s| s.channel_id != 99);
+    maker_with(&fs, config).make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(fs.get(Path::new(ROOT).join("channel99")), None);
+    assert!(fs.get(Path::new(ROOT).join("channel1")).is_some());
+}
+
+#[test]
+fn failure_while_staging_leaves_the_tree_untouched() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    let before = fs.tree(ROOT);
+
+    assert!(failing_maker(&fs, "part2/CONFIG")
+        .make(Cleanup::Remove)
+        .is_err());
+
+    assert_eq!(fs.tree(ROOT), before);
+}
+
+#[test]
+fn failure_while_swapping_restores_the_tree() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    let before = fs.tree(ROOT);
+
+    // the staged `channel1` is moved in after `auth` and the old entries were moved aside
+    match failing_maker(&fs, "example/channel1").make(Cleanup::Refuse) {
+        Err(MakerError::Move { .. }) => {}
+        other => panic!("{:?}", other),
+    }
+
+    assert_eq!(fs.tree(ROOT), before);
+}
+
+#[test]
+fn failure_while_swapping_restores_backed_up_entries() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing_without_manifest(&fs);
+    let before = fs.tree(ROOT);
+
+    // everything that is not whitelisted was moved into the backup before `channel1` fails
+    match failing_maker(&fs, "example/channel1").make(Cleanup::Backup) {
+        Err(MakerError::Move { .. }) => {}
+        other => panic!("{:?}", other),
+    }
+
+    let mut after = fs.tree(ROOT);
+    after.retain(|p, _| !p.starts_with("backups"));
+    assert_eq!(after, before);
+}
+
+#[test]
+fn verify_inspects_the_tree_through_the_file_system() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    fs.remove(&Path::new(ROOT).join("channel1/part2/mark"))
+        .unwrap();
+    fs.remove(&Path::new(ROOT).join("auth/1/locale")).unwrap();
+    fs.symlink(
+        Path::new("../../share/package"),
+        &Path::new(ROOT).join("auth/1/locale"),
+    )
+    .unwrap();
+
+    let drift = maker(&fs)
+        .verify()
+        .unwrap()
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        drift,
+        vec![
+            "auth/1/locale: points to ../../share/package instead of ../../share/locale",
+            "channel1/part1/CONFIG: contents changed",
+            "channel1/part2/mark: missing",
+        ]
+    );
+}
+
+#[test]
+fn extra_keys_replace_the_template_lines_they_match() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    share(&fs);
+    let mut config = config();
+    config
+        .common
+        .extra
+        .insert("TEST_SERVER".to_string(), serde_json::json!(1));
+    config
+        .common
+        .extra
+        .insert("NEW_KEY".to_string(), serde_json::json!("x"));
+
+    maker_with(&fs, config).make(Cleanup::Refuse).unwrap();
+
+    let part1 = file(&fs, "channel1/part1/CONFIG");
+    assert_eq!(part1.matches("TEST_SERVER").count(), 1, "{}", part1);
+    assert!(part1.contains("TEST_SERVER: 1\n"), "{}", part1);
+    assert!(part1.ends_with("NEW_KEY: x\n"), "{}", part1);
+}
+
+#[test]
+fn changes_are_found_and_applied_through_the_file_system() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    fs.remove(&Path::new(ROOT).join("channel99")).unwrap();
+    let maker = maker(&fs);
+
+    let changes = maker.changes(Cleanup::Refuse).unwrap();
+    let shown = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    assert!(
+        shown.contains(&"~ channel1/part1/CONFIG".to_string()),
+        "{:?}",
+        shown
+    );
+    assert!(shown.contains(&"+ channel99".to_string()), "{:?}", shown);
+
+    maker.apply(&changes, Cleanup::Refuse).unwrap();
+    assert!(file(&fs, "channel1/part1/CONFIG").contains("PORT: 61000\n"));
+    assert!(maker.changes(Cleanup::Refuse).unwrap().is_empty());
+}
+
+#[test]
+fn diff_inspects_the_tree_through_the_file_system() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    fs.remove(&Path::new(ROOT).join("channel1/part2/mark"))
+        .unwrap();
+
+    let diff = maker(&fs).diff().unwrap();
+    assert_eq!(diff.len(), 2, "{:?}", diff);
+    assert!(diff[0].starts_with(
+        "--- channel1/part1/CONFIG (on disk)\n+++ channel1/part1/CONFIG (from config)\n"
+    ));
+    assert!(diff[0].contains("\n-old\n+CHANNEL: 1\n"), "{}", diff[0]);
+    assert_eq!(diff[1], "missing directory channel1/part2/mark");
+}
+
+#[test]
+fn make_puts_back_what_an_interrupted_run_left() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    // interrupted after the log was carried over and `channel99` was moved aside
+    let (staging, previous) = (
+        Path::new(ROOT).join(".channels-maker.staging"),
+        Path::new(ROOT).join(".channels-maker.previous"),
+    );
+    fs.create_dir_all(&staging.join("channel1/part1/log"))
+        .unwrap();
+    fs.rename(
+        &Path::new(ROOT).join("channel1/part1/log/syslog"),
+        &staging.join("channel1/part1/log/syslog"),
+    )
+    .unwrap();
+    fs.insert(
+        Path::new(ROOT).join("channel99/part1/log/syslog"),
+        Node::File {
+            contents: b"booted 99".to_vec(),
+            executable: false,
+        },
+    );
+    fs.create_dir(&previous).unwrap();
+    fs.rename(
+        &Path::new(ROOT).join("channel99"),
+        &previous.join("channel99"),
+    )
+    .unwrap();
+
+    maker(&fs).make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(file(&fs, "channel1/part1/log/syslog"), "booted");
+    assert_eq!(file(&fs, "channel99/part1/log/syslog"), "booted 99");
+    assert_eq!(fs.get(&staging), None);
+    assert_eq!(fs.get(&previous), None);
+}
+
+#[test]
+fn make_refuses_to_pick_between_an_entry_and_its_replacement() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    let previous = Path::new(ROOT).join(".channels-maker.previous");
+    fs.create_dir_all(&previous.join("channel1")).unwrap();
+    fs.create_dir_all(&Path::new(ROOT).join(".channels-maker.staging/auth"))
+        .unwrap();
+    let before = fs.tree(ROOT);
+
+    match maker(&fs).make(Cleanup::Refuse) {
+        Err(MakerError::Interrupted { path, from }) => {
+            assert_eq!(path, Path::new(ROOT).join("channel1"));
+            assert_eq!(from, previous.join("channel1"));
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(fs.tree(ROOT), before);
+}
+
+#[test]
+fn backup_keeps_the_replaced_tree_when_there_is_a_manifest() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+
+    let mut config = config();
+    config.channels.settings.retain(|s| s.channel_id != 99);
+    maker_with(&fs, config).make(Cleanup::Backup).unwrap();
+
+    let backups = fs
+        .read_dir(&Path::new(ROOT).join("backups"))
+        .unwrap_or_default();
+    assert_eq!(backups.len(), 1, "{:?}", backups);
+    let backup = Path::new("backups").join(&backups[0].0);
+    assert_eq!(
+        file(&fs, &backup.join("channel1/part1/CONFIG").to_string_lossy()),
+        "old"
+    );
+    assert!(fs
+        .get(Path::new(ROOT).join(&backup).join("channel99"))
+        .is_some());
+    assert_eq!(fs.get(Path::new(ROOT).join("channel99")), None);
+    // runtime output stays with the new tree, unrelated files are left alone
+    assert_eq!(file(&fs, "channel1/part1/log/syslog"), "booted");
+    assert_eq!(file(&fs, "notes.txt"), "keep me");
+}
+
+#[test]
+fn plan_lists_generated_paths_the_config_does_not_describe_anymore() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing(&fs);
+    let mut config = config();
+    config.channels.settings.retain(|s| s.channel_id != 99);
+    let maker = maker_with(&fs, config);
+
+    let plan = maker.plan(Cleanup::Refuse).unwrap();
+    assert_eq!(plan.remove, vec![PathBuf::from("channel99")]);
+    assert_eq!(plan.backup, None);
+    assert!(maker.plan(Cleanup::Backup).unwrap().backup.is_some());
+}
+
+#[test]
+fn apply_without_a_manifest_leaves_unrelated_entries_alone() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    existing_without_manifest(&fs);
+    fs.insert(Path::new(ROOT).join("quest/scripts"), Node::Directory);
+    let maker = maker(&fs);
+
+    let changes = maker.changes(Cleanup::Refuse).unwrap();
+    maker.apply(&changes, Cleanup::Refuse).unwrap();
+    assert!(fs.get(Path::new(ROOT).join("quest/scripts")).is_some());
+    assert!(file(&fs, "channel1/part1/CONFIG").contains("PORT: 61000\n"));
+
+    let changes = maker.changes(Cleanup::Backup).unwrap();
+    let shown = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    assert_eq!(shown, vec!["- quest"]);
+    maker.apply(&changes, Cleanup::Backup).unwrap();
+    assert_eq!(fs.get(Path::new(ROOT).join("quest")), None);
+    let backups = fs.read_dir(&Path::new(ROOT).join("backups")).unwrap();
+    let backup = Path::new(ROOT).join("backups").join(&backups[0].0);
+    assert!(fs.get(backup.join("quest/scripts")).is_some());
+}
+
+#[test]
+fn maps_are_checked_against_the_index_in_the_file_system() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    share(&fs);
+    fs.insert(
+        Path::new(ROOT).join("share/locale/poland/map/index"),
+        Node::File {
+            contents: b"1\tmap_a1\n2\tmap_a2\n".to_vec(),
+            executable: false,
+        },
+    );
+
+    match maker(&fs).make(Cleanup::Refuse) {
+        Err(MakerError::Invalid { report }) => {
+            let report = report.to_string();
+            assert!(
+                report.contains(
+                    "error: channels.common_maps[0].maps: map 3 is not in the poland map index"
+                ),
+                "{}",
+                report
+            );
+            assert!(!report.contains("cannot read"), "{}", report);
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+/// What `verify` and `diff` report for the tree `maker` generated, one line each.
+fn drift(maker: &Maker) -> Vec<String> {
+    let mut lines = maker
+        .verify()
+        .unwrap()
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>();
+    lines.extend(maker.diff().unwrap());
+
+    lines
+}
+
+/// The example config deploying the share directory of game cores and db with `game`/`db`.
+fn deployed(game: DeployMode, db: DeployMode) -> Config {
+    let mut config = config();
+    config.share.deploy.game = game;
+    config.share.deploy.db = db;
+
+    config
+}
+
+#[test]
+fn copies_are_verified_and_refreshed_only_when_the_source_changed() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    share(&fs);
+    // the manifest is read when the maker is created
+    let maker = || maker_with(&fs, deployed(DeployMode::Copy, DeployMode::Symlink));
+    maker().make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(file(&fs, "channel1/part1/CMD"), "");
+    assert_eq!(
+        fs.get(Path::new(ROOT).join("channel1/part1/data")),
+        Some(Node::Directory)
+    );
+    assert_eq!(drift(&maker()), Vec::<String>::new());
+
+    fs.write(&Path::new(ROOT).join("share/CMD"), b"changed")
+        .unwrap();
+    let changes = maker().changes(Cleanup::Refuse).unwrap();
+    let shown = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    assert!(
+        shown.contains(&"~ channel1/part1/CMD".to_string()),
+        "{:?}",
+        shown
+    );
+    assert!(shown.iter().all(|c| c.ends_with("/CMD")), "{:?}", shown);
+    assert!(drift(&maker()).contains(&"channel1/part1/CMD: differs from share/CMD".to_string()));
+
+    maker().apply(&changes, Cleanup::Refuse).unwrap();
+    assert_eq!(file(&fs, "channel1/part1/CMD"), "changed");
+    assert!(maker().changes(Cleanup::Refuse).unwrap().is_empty());
+}
+
+#[test]
+fn hard_links_are_verified_and_restored() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    share(&fs);
+    let maker = || maker_with(&fs, deployed(DeployMode::Hardlink, DeployMode::Hardlink));
+    maker().make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(
+        fs.get(Path::new(ROOT).join("channel1/part1/CMD")),
+        Some(Node::Hardlink(Path::new(ROOT).join("share/CMD")))
+    );
+    assert_eq!(drift(&maker()), Vec::<String>::new());
+
+    // an editor that saves by replacing the file breaks the link
+    let path = Path::new(ROOT).join("channel1/part1/CMD");
+    fs.remove(&path).unwrap();
+    fs.write(&path, b"edited").unwrap();
+    assert_eq!(
+        drift(&maker()),
+        vec![
+            "channel1/part1/CMD: not hard linked to share/CMD anymore",
+            "channel1/part1/CMD is not hard linked to share/CMD",
+        ]
+    );
+
+    let changes = maker().changes(Cleanup::Refuse).unwrap();
+    maker().apply(&changes, Cleanup::Refuse).unwrap();
+    assert_eq!(drift(&maker()), Vec::<String>::new());
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use channels_maker::{Plan, CONFIG_FILE};
+use serde_json::{json, Value};
+
+mod common;
+
+fn plan() -> Plan {
+    let mut plan = Plan::default();
+    plan.directory("db");
+    plan.file("db/conf.txt", "BIND_PORT = 15000\n".to_string());
+    plan.symlink("../share/db", "db/db_example");
+    plan.directory("channel1");
+    plan.directory("channel1/part1");
+    plan.preserved_directory("channel1/part1/log");
+    plan.hardlink("share/CMD", "channel1/part1/CMD");
+    plan.copy("share/data", "channel1/part1/data");
+
+    plan
+}
+
+#[test]
+fn tree_lists_entries_sorted_below_their_directory() {
+    assert_eq!(
+        plan().to_tree(),
+        "create:
+  channel1/
+    part1/
+      CMD (hardlink of share/CMD)
+      data (copy of share/data)
+      log/
+  db/
+    conf.txt (18 bytes)
+    db_example -> ../share/db
+"
+    );
+}
+
+#[test]
+fn tree_lists_removed_paths_first() {
+    let mut plan = plan();
+    plan.remove = vec![PathBuf::from("old/notes.txt"), PathBuf::from("stray")];
+    assert!(plan
+        .to_tree()
+        .starts_with("remove:\n  old/\n    notes.txt\n  stray\ncreate:\n"));
+
+    plan.backup = Some(PathBuf::from("backups/20261018-120000"));
+    assert!(plan
+        .to_tree()
+        .starts_with("move to backups/20261018-120000:\n  old/\n"));
+}
+
+#[test]
+fn json_keeps_the_order_of_the_plan() {
+    let mut plan = plan();
+    plan.remove = vec![PathBuf::from("stray")];
+    let actual: Value = serde_json::from_str(&plan.to_json()).unwrap();
+
+    assert_eq!(
+        actual,
+        json!({
+            "remove": ["stray"],
+            "create": [
+                {"kind": "directory", "path": "db"},
+                {"kind": "file", "path": "db/conf.txt", "contents": "BIND_PORT = 15000\n"},
+                {"kind": "symlink", "path": "db/db_example", "target": "../share/db"},
+                {"kind": "directory", "path": "channel1"},
+                {"kind": "directory", "path": "channel1/part1"},
+                {"kind": "directory", "path": "channel1/part1/log", "preserve": true},
+                {"kind": "hardlink", "path": "channel1/part1/CMD", "source": "share/CMD"},
+                {"kind": "copy", "path": "channel1/part1/data", "source": "share/data"},
+            ],
+        })
+    );
+}
+
+/// Runs the binary with `args` in a server directory holding the example config and share.
+fn dry_run(args: &[&str]) -> (tempfile::TempDir, String) {
+    let dir = tempfile::tempdir().unwrap();
+    common::share(dir.path());
+    fs::copy("config.example.json", dir.path().join(CONFIG_FILE)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_channels-maker"))
+        .arg("--root")
+        .arg(dir.path())
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    (dir, String::from_utf8(output.stdout).unwrap())
+}
+
+/// Names in the server directory besides the share and the config.
+fn generated(dir: &tempfile::TempDir) -> Vec<String> {
+    let mut names = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|n| n != "share" && n != CONFIG_FILE)
+        .collect::<Vec<_>>();
+    names.sort();
+
+    names
+}
+
+#[test]
+fn dry_run_prints_the_plan_without_touching_the_disk() {
+    let (dir, out) = dry_run(&["--dry-run"]);
+
+    assert!(out.starts_with("create:\n  .channels-maker.lock.json ("));
+    assert!(out.contains("\n  channel1/\n    part1/\n      CMD -> ../../share/CMD\n"));
+    assert_eq!(generated(&dir), Vec::<String>::new());
+}
+
+#[test]
+fn dry_run_prints_json_on_request() {
+    let (dir, out) = dry_run(&["--dry-run", "--format", "json"]);
+
+    let plan: Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(plan["remove"], json!([]));
+    assert!(plan["create"]
+        .as_array()
+        .unwrap()
+        .contains(&json!({"kind": "symlink", "path": "db/db_example", "target": "../share/db"})));
+    assert_eq!(generated(&dir), Vec::<String>::new());
+}
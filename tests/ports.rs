@@ -0,0 +1,86 @@
+use channels_maker::config::AllocatedPorts;
+use channels_maker::ports::PortLock;
+use channels_maker::Config;
+use serde_json::{json, Value};
+
+/// The example config with channel 1 left without ports and `game`/`p2p` ranges starting at
+/// `game_start`/`p2p_start`.
+fn config(game_start: i64, p2p_start: i64) -> Config {
+    let mut config: Value = serde_json::from_str(include_str!("../config.example.json")).unwrap();
+    let channel1 = config["channels"]["settings"][0].as_object_mut().unwrap();
+    channel1.remove("port");
+    channel1.remove("p2p_port");
+    config["port_ranges"] = json!({
+        "game": {"start": game_start, "end": 61999},
+        "p2p": {"start": p2p_start, "end": 62999}
+    });
+
+    Config::parse(&config.to_string()).unwrap()
+}
+
+fn ports(config: &Config, key: &str) -> (i64, i64) {
+    let a = &config.allocated_ports[key];
+    (a.port, a.p2p_port)
+}
+
+fn lock(entries: &[(&str, i64, i64)]) -> PortLock {
+    entries
+        .iter()
+        .map(|(key, port, p2p_port)| {
+            (
+                key.to_string(),
+                AllocatedPorts {
+                    port: *port,
+                    p2p_port: *p2p_port,
+                },
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn ports_used_explicitly_are_skipped() {
+    // channel 98 (api) and 99 take 61098 and 61099, channel 2 61100 and 61101
+    let mut config = config(61098, 62098);
+    config.allocate_ports(&PortLock::new()).unwrap();
+
+    assert_eq!(ports(&config, "game1_1"), (61102, 62102));
+    assert_eq!(ports(&config, "game1_2"), (61103, 62103));
+    assert_eq!(config.allocated_ports.len(), 2);
+}
+
+#[test]
+fn locked_ports_are_kept() {
+    let mut config = config(61000, 62000);
+    config
+        .allocate_ports(&lock(&[("game1_2", 61500, 62500)]))
+        .unwrap();
+
+    assert_eq!(ports(&config, "game1_1"), (61000, 62000));
+    assert_eq!(ports(&config, "game1_2"), (61500, 62500));
+}
+
+#[test]
+fn locked_ports_taken_by_explicit_ones_are_reallocated() {
+    let mut config = config(61000, 62000);
+    config
+        .allocate_ports(&lock(&[
+            ("game1_1", 61100, 62600),
+            ("game1_2", 70000, 62601),
+        ]))
+        .unwrap();
+
+    // 61100 belongs to channel 2 now, 70000 is out of range
+    assert_eq!(ports(&config, "game1_1"), (61000, 62600));
+    assert_eq!(ports(&config, "game1_2"), (61001, 62601));
+}
+
+#[test]
+fn allocation_is_deterministic() {
+    let mut first = config(61000, 62000);
+    let mut second = config(61000, 62000);
+    first.allocate_ports(&PortLock::new()).unwrap();
+    second.allocate_ports(&PortLock::new()).unwrap();
+
+    assert_eq!(first.port_lock_json(), second.port_lock_json());
+}
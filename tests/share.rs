@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use channels_maker::filesystem::Node;
+use channels_maker::maker::MakerError;
+use channels_maker::{Cleanup, Config, FileSystem, Maker, MemoryFileSystem};
+use serde_json::{json, Value};
+
+mod common;
+
+const ROOT: &str = "/srv/example";
+
+/// The example config with its `share` object replaced by `share`.
+fn config(share: Value) -> Config {
+    let mut config: Value = serde_json::from_str(include_str!("../config.example.json")).unwrap();
+    config["share"] = share;
+    Config::parse(&config.to_string()).unwrap()
+}
+
+fn maker(fs: &Rc<MemoryFileSystem>, config: Config) -> Maker {
+    Maker::with_file_system(
+        config,
+        PathBuf::from(ROOT),
+        &Path::new(ROOT).join("config.json"),
+        None,
+        Box::new(Rc::clone(fs)),
+    )
+    .unwrap()
+}
+
+fn link(fs: &MemoryFileSystem, path: &str) -> PathBuf {
+    fs.read_link(&Path::new(ROOT).join(path)).unwrap()
+}
+
+fn executable() -> Node {
+    Node::File {
+        contents: vec![],
+        executable: true,
+    }
+}
+
+#[test]
+fn share_can_live_outside_the_server_directory() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    fs.insert(ROOT, Node::Directory);
+    common::share_in_memory(&fs, Path::new("/srv/assets"));
+    let config = config(json!({ "path": "/srv/assets/share" }));
+    maker(&fs, config).make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(fs.get(Path::new(ROOT).join("share")), None);
+    assert_eq!(
+        link(&fs, "channel1/part1/data"),
+        Path::new("/srv/assets/share/data")
+    );
+    assert_eq!(
+        link(&fs, "db/db_example"),
+        Path::new("/srv/assets/share/db")
+    );
+}
+
+#[test]
+fn relative_share_paths_are_linked_relative_to_each_directory() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    common::share_in_memory(&fs, &Path::new(ROOT).join("assets"));
+    let config = config(json!({ "path": "assets/share" }));
+    maker(&fs, config).make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(
+        link(&fs, "channel1/part1/data"),
+        Path::new("../../assets/share/data")
+    );
+    assert_eq!(link(&fs, "db/locale"), Path::new("../assets/share/locale"));
+}
+
+#[test]
+fn custom_link_lists_and_binaries_replace_the_defaults() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    let share = Path::new(ROOT).join("share");
+    fs.insert(share.join("quest"), Node::Directory);
+    fs.insert(share.join("game_custom"), executable());
+    fs.insert(share.join("db_custom"), executable());
+    let config = config(json!({
+        "game": ["quest"],
+        "auth": [],
+        "db": [{ "name": "quest", "kind": "directory" }],
+        "game_binary": "game_custom",
+        "db_binary": "db_custom",
+    }));
+    maker(&fs, config).make(Cleanup::Refuse).unwrap();
+
+    assert_eq!(
+        link(&fs, "channel1/part1/quest"),
+        Path::new("../../share/quest")
+    );
+    assert_eq!(
+        link(&fs, "channel1/part1/game1_1"),
+        Path::new("../../share/game_custom")
+    );
+    assert_eq!(
+        link(&fs, "auth/1/auth1"),
+        Path::new("../../share/game_custom")
+    );
+    assert_eq!(link(&fs, "db/quest"), Path::new("../share/quest"));
+    assert_eq!(link(&fs, "db/db_example"), Path::new("../share/db_custom"));
+    for unlisted in &["channel1/part1/data", "channel1/part1/CMD", "auth/1/data"] {
+        assert_eq!(fs.get(Path::new(ROOT).join(unlisted)), None, "{}", unlisted);
+    }
+}
+
+#[test]
+fn share_entries_are_checked_against_their_kind() {
+    let fs = Rc::new(MemoryFileSystem::new());
+    let share = Path::new(ROOT).join("share");
+    fs.insert(share.join("data"), executable());
+    fs.insert(share.join("CMD"), Node::Directory);
+    fs.insert(share.join("run"), Node::Directory);
+    fs.insert(
+        share.join("notes"),
+        Node::File {
+            contents: vec![],
+            executable: false,
+        },
+    );
+    fs.insert(share.join("game_example"), executable());
+    fs.insert(share.join("db"), Node::Directory);
+    let config = config(json!({
+        "game": [
+            { "name": "data", "kind": "directory" },
+            "CMD",
+            { "name": "run", "kind": "file" },
+            { "name": "notes", "kind": "executable" },
+            { "name": "missing" },
+        ],
+        "auth": [],
+        "db": [],
+    }));
+    let problems = match maker(&fs, config).make(Cleanup::Refuse) {
+        Err(MakerError::Share { problems }) => problems,
+        other => panic!("unexpected result: {:?}", other.err()),
+    };
+
+    assert_eq!(
+        problems,
+        vec![
+            "share/data: not a directory",
+            "share/run: not a file",
+            "share/notes: not executable",
+            "share/missing: missing",
+            "share/db: not a file",
+        ]
+    );
+    assert_eq!(fs.get(Path::new(ROOT).join("channel1")), None);
+}
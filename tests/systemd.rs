@@ -0,0 +1,64 @@
+use std::fs;
+
+use channels_maker::systemd::{install, units};
+use channels_maker::Config;
+
+fn config() -> Config {
+    Config::parse(include_str!("../config.example.json")).unwrap()
+}
+
+#[test]
+fn install_writes_every_unit_into_the_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let unit_dir = dir.path().join("etc/systemd/system");
+    let units = units(&config());
+
+    install(&units, &unit_dir).unwrap();
+
+    let mut names = fs::read_dir(&unit_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    let mut expected = units.iter().map(|u| u.name.clone()).collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(names, expected);
+
+    for u in units.iter() {
+        assert_eq!(
+            fs::read_to_string(unit_dir.join(&u.name)).unwrap(),
+            u.contents
+        );
+    }
+}
+
+#[test]
+fn install_overwrites_existing_units() {
+    let dir = tempfile::tempdir().unwrap();
+    let units = units(&config());
+    fs::write(dir.path().join(&units[0].name), "stale").unwrap();
+
+    install(&units, dir.path()).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join(&units[0].name)).unwrap(),
+        units[0].contents
+    );
+}
+
+#[test]
+fn cores_require_db_and_the_target_wants_every_service() {
+    let units = units(&config());
+    let target = units.last().unwrap();
+
+    assert_eq!(target.name, "example.target");
+    assert_eq!(units[0].name, "example-db.service");
+    for u in units[1..units.len() - 1].iter() {
+        assert!(
+            u.contents.contains("Requires=example-db.service\n"),
+            "{}",
+            u.name
+        );
+        assert!(target.contents.contains(&u.name), "{}", u.name);
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use channels_maker::maker::MakerError;
+use channels_maker::template::{TemplateError, Templates, Variables, GAME_CONFIG};
+use channels_maker::{Cleanup, Config, FileSystem, Maker, MemoryFileSystem};
+
+mod common;
+
+const ROOT: &str = "/srv/example";
+
+fn maker(fs: &Rc<MemoryFileSystem>, template_dir: &Path) -> Result<Maker, MakerError> {
+    Maker::with_file_system(
+        Config::parse(include_str!("../config.example.json")).unwrap(),
+        PathBuf::from(ROOT),
+        &Path::new(ROOT).join("config.json"),
+        Some(template_dir),
+        Box::new(Rc::clone(fs)),
+    )
+}
+
+fn read(fs: &MemoryFileSystem, path: &str) -> String {
+    String::from_utf8(fs.read(&Path::new(ROOT).join(path)).unwrap()).unwrap()
+}
+
+/// Renders `source` as the game CONFIG template with only `hostname` set.
+fn render(source: &str) -> Result<String, TemplateError> {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(GAME_CONFIG), source).unwrap();
+    let mut variables = Variables::new();
+    variables.insert("hostname", "part1".to_string());
+
+    Templates::load(Some(dir.path()))
+        .unwrap()
+        .render(GAME_CONFIG, &variables)
+}
+
+#[test]
+fn a_template_in_the_user_directory_replaces_the_builtin_one() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join(GAME_CONFIG),
+        "HOSTNAME: {{ hostname }}\nMAP_ALLOW:{{map_allow}}\n",
+    )
+    .unwrap();
+    let fs = Rc::new(MemoryFileSystem::new());
+    common::share_in_memory(&fs, Path::new(ROOT));
+    maker(&fs, dir.path())
+        .unwrap()
+        .make(Cleanup::Refuse)
+        .unwrap();
+
+    assert_eq!(
+        read(&fs, "channel1/part2/CONFIG"),
+        "HOSTNAME: part2\nMAP_ALLOW: 4 5 6\n"
+    );
+    // templates missing from the directory stay built in
+    assert!(read(&fs, "auth/1/CONFIG").contains("\nHOSTNAME: auth1\n"));
+    assert!(read(&fs, "db/conf.txt").starts_with("BIND_PORT = 15000\n"));
+}
+
+#[test]
+fn unknown_variables_are_reported_with_their_line() {
+    match render("HOSTNAME: {{ hostname }}\nMAX_USER: {{ max_user }}\n") {
+        Err(TemplateError::Unknown {
+            template,
+            line,
+            name,
+        }) => {
+            assert_eq!(template, GAME_CONFIG);
+            assert_eq!(line, 2);
+            assert_eq!(name, "max_user");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn unclosed_variables_are_reported_with_their_line() {
+    match render("HOSTNAME: {{ hostname }}\n\nPORT: {{ port\n}}\n") {
+        Err(TemplateError::Unclosed { template, line }) => {
+            assert_eq!(template, GAME_CONFIG);
+            assert_eq!(line, 3);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn template_errors_stop_the_maker_before_anything_is_written() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(GAME_CONFIG), "PORT: {{ prot }}\n").unwrap();
+    let fs = Rc::new(MemoryFileSystem::new());
+    common::share_in_memory(&fs, Path::new(ROOT));
+
+    let err = maker(&fs, dir.path())
+        .unwrap()
+        .make(Cleanup::Refuse)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "template game.CONFIG line 1: unknown variable \"prot\""
+    );
+    for path in &["channel1", "auth", "db", "start.sh"] {
+        assert_eq!(fs.get(Path::new(ROOT).join(path)), None, "{}", path);
+    }
+}
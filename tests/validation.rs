@@ -0,0 +1,174 @@
+use channels_maker::validation::validate;
+use channels_maker::{Config, RealFileSystem};
+use serde_json::{json, Value};
+use std::fs;
+
+/// The example config with `ranges` added to its channels.
+fn config(ranges: Value) -> Config {
+    edited(|config| config["channels"]["ranges"] = ranges)
+}
+
+/// The example config after `edit`.
+fn edited<F: FnOnce(&mut Value)>(edit: F) -> Config {
+    let mut config: Value = serde_json::from_str(include_str!("../config.example.json")).unwrap();
+    edit(&mut config);
+
+    Config::parse(&config.to_string()).unwrap()
+}
+
+fn problems(config: &Config) -> Vec<String> {
+    problems_in(config, "/nonexistent".as_ref())
+}
+
+fn problems_in(config: &Config, root: &std::path::Path) -> Vec<String> {
+    validate(config, &RealFileSystem, root)
+        .problems
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+/// Problems of `config` without the warnings, e.g. the one about the missing map index.
+fn errors(config: &Config) -> Vec<String> {
+    let mut problems = problems(config);
+    problems.retain(|p| p.starts_with("error: "));
+
+    problems
+}
+
+#[test]
+fn range_channels_are_reported_at_their_range() {
+    let config = config(json!([
+        {"from": 3, "to": 3, "port": 61000, "p2p_port": 63000},
+        {"from": 3, "to": 3, "port": 61500, "p2p_port": 63500},
+    ]));
+
+    let problems = problems(&config);
+    assert!(problems.contains(
+        &"error: channels.ranges[0].port (channel 3, part 1): \
+          port 61000 is already used by channels.settings[0].port (part 1)"
+            .to_string()
+    ));
+    assert!(problems.contains(
+        &"error: channels.ranges[1].channel_id (channel 3): \
+          channel id 3 is already used by channels.ranges[0].channel_id (channel 3)"
+            .to_string()
+    ));
+    assert!(!problems.iter().any(|p| p.contains("channels.settings[4]")));
+}
+
+#[test]
+fn maps_a_channel_does_not_host_are_reported_per_channel() {
+    let root = tempfile::tempdir().unwrap();
+    let index = root.path().join("share/locale/poland/map");
+    fs::create_dir_all(&index).unwrap();
+    let maps = [1, 2, 3, 4, 5, 6, 81];
+    let lines = maps.iter().map(|m| format!("{}\tmap_{}\n", m, m));
+    fs::write(index.join("index"), lines.collect::<String>()).unwrap();
+
+    let config = config(json!([]));
+    assert_eq!(
+        problems_in(&config, root.path()),
+        vec![
+            "warning: channels.settings[0]: maps 81 are allowed on no part",
+            "warning: channels.settings[1]: maps 81 are allowed on no part",
+            "warning: channels.settings[2]: maps 1 2 3 4 5 6 81 are allowed on no part",
+            "warning: channels.settings[3]: maps 1 2 3 4 5 6 are allowed on no part",
+        ]
+    );
+}
+
+#[test]
+fn extra_values_with_line_breaks_are_rejected() {
+    let mut config = config(json!([]));
+    config
+        .common
+        .extra
+        .insert("X".to_string(), json!("1\nPORT: 1"));
+
+    assert!(problems(&config).contains(
+        &"error: common.extra.X: value has to be a string without line breaks, number or boolean"
+            .to_string()
+    ));
+}
+
+#[test]
+fn duplicate_channel_ids_are_reported_at_the_later_setting() {
+    let config = edited(|c| c["channels"]["settings"][1]["channel_id"] = json!(1));
+
+    assert_eq!(
+        errors(&config),
+        vec![
+            "error: channels.settings[1].channel_id: \
+             channel id 1 is already used by channels.settings[0].channel_id",
+            "error: channels.settings[1]: \
+             directory \"channel1\" is already used by channels.settings[0]",
+        ]
+    );
+}
+
+#[test]
+fn p2p_ports_clashing_with_auth_ports_are_reported() {
+    let config = edited(|c| c["channels"]["settings"][0]["p2p_port"] = json!(60000));
+
+    assert_eq!(
+        errors(&config),
+        vec![
+            "error: auth.ports[0].port: \
+              port 60000 is already used by channels.settings[0].p2p_port (part 1)"
+        ]
+    );
+}
+
+#[test]
+fn part_fields_are_reported_with_their_part() {
+    let config = edited(|c| {
+        c["channels"]["settings"][3]["override_maps"] =
+            json!([[81], {"maps": [], "port": 62099, "p2p_port": 63099}])
+    });
+
+    assert_eq!(
+        errors(&config),
+        vec![
+            "error: channels.settings[3].override_maps[1].port: \
+              port 62099 is already used by channels.settings[3].p2p_port (part 1)"
+        ]
+    );
+}
+
+#[test]
+fn item_id_ranges_have_to_start_before_their_end() {
+    let config = edited(|c| c["db"]["item_id_range"] = json!({"start": 20000000, "end": 10000001}));
+
+    assert_eq!(
+        errors(&config),
+        vec!["error: db.item_id_range: start 20000000 is greater than end 10000001"]
+    );
+}
+
+#[test]
+fn port_strides_out_of_range_are_reported_without_overflowing() {
+    let config = edited(|c| {
+        c["channels"]["port_stride"] = json!(i64::MAX);
+        c["channels"]["settings"][0]["port_stride"] = json!(0);
+        c["channels"]["ranges"] = json!([
+            {"from": 3, "to": 4, "port": 61200, "p2p_port": 62200, "port_stride": -100},
+        ]);
+    });
+    let errors = errors(&config);
+
+    for expected in &[
+        "error: channels.port_stride: stride 9223372036854775807 is not between 1 and 65535",
+        "error: channels.settings[0].port_stride: stride 0 is not between 1 and 65535",
+        "error: channels.ranges[0].port_stride: stride -100 is not between 1 and 65535",
+        "error: channels.settings[1].port (part 2): port 9223372036854775807 is out of range",
+    ] {
+        assert!(errors.contains(&expected.to_string()), "{:?}", errors);
+    }
+    assert_eq!(
+        errors.iter().filter(|e| e.contains("stride")).count(),
+        3,
+        "{:?}",
+        errors
+    );
+}
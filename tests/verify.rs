@@ -0,0 +1,90 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use channels_maker::manifest::MANIFEST_FILE;
+use channels_maker::CONFIG_FILE;
+
+mod common;
+
+use common::{generated, maker};
+
+/// Drift of a freshly generated tree after `edit`, one line each.
+fn drift_after<F: FnOnce(&Path)>(edit: F) -> Vec<String> {
+    let dir = generated(edit);
+    let root = dir.path();
+
+    maker(root)
+        .verify()
+        .unwrap()
+        .iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+#[test]
+fn fresh_tree_has_no_drift() {
+    assert_eq!(drift_after(|_| {}), Vec::<String>::new());
+}
+
+#[test]
+fn hand_edits_and_deleted_entries_are_reported() {
+    let drift = drift_after(|root| {
+        fs::write(root.join("channel1/part2/CONFIG"), "PORT: 1\n").unwrap();
+        fs::remove_file(root.join("channel1/part1/data")).unwrap();
+        fs::remove_file(root.join("auth/1/locale")).unwrap();
+        std::os::unix::fs::symlink("../../share/package", root.join("auth/1/locale")).unwrap();
+    });
+
+    assert_eq!(
+        drift,
+        vec![
+            "auth/1/locale: points to ../../share/package instead of ../../share/locale",
+            "channel1/part1/data: missing",
+            "channel1/part2/CONFIG: contents changed",
+        ]
+    );
+}
+
+#[test]
+fn config_changes_are_reported() {
+    let drift = drift_after(|root| {
+        let config = fs::read_to_string(root.join(CONFIG_FILE)).unwrap();
+        fs::write(
+            root.join(CONFIG_FILE),
+            config.replacen("\"max_level\": 105", "\"max_level\": 120", 1),
+        )
+        .unwrap();
+    });
+
+    assert_eq!(drift, vec!["config changed since the tree was generated"]);
+}
+
+/// Exit status of the binary running `subcommand` in `root`.
+fn status(root: &Path, subcommand: &str) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_channels-maker"))
+        .arg("--root")
+        .arg(root)
+        .arg(subcommand)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn exit_status_tells_drift_from_errors() {
+    let dir = generated(|_| {});
+    let root = dir.path();
+    assert_eq!(status(root, "verify"), Some(0));
+    assert_eq!(status(root, "diff"), Some(0));
+
+    fs::write(root.join("channel1/part2/CONFIG"), "PORT: 1\n").unwrap();
+    assert_eq!(status(root, "verify"), Some(1));
+    assert_eq!(status(root, "diff"), Some(1));
+
+    fs::remove_file(root.join(MANIFEST_FILE)).unwrap();
+    assert_eq!(status(root, "verify"), Some(2));
+    fs::remove_file(root.join(CONFIG_FILE)).unwrap();
+    assert_eq!(status(root, "diff"), Some(2));
+}